@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE invite ADD COLUMN accepted_by BLOB REFERENCES user(id) ON DELETE SET NULL;
@@ -0,0 +1,13 @@
+-- Add migration script here
+-- Subjects are only unique per provider, so key accounts on both
+CREATE TABLE account_new(
+	id TEXT NOT NULL,
+	provider TEXT NOT NULL,
+	user_id BLOB NOT NULL,
+	PRIMARY KEY (provider, id),
+	FOREIGN KEY(user_id) REFERENCES user(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+INSERT INTO account_new (id, provider, user_id) SELECT id, provider, user_id FROM account;
+DROP TABLE account;
+ALTER TABLE account_new RENAME TO account;
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- Only set for accounts with the local username/password provider
+ALTER TABLE account ADD COLUMN password_hash TEXT;
@@ -0,0 +1,10 @@
+-- Add migration script here
+-- Only a hash of each token is stored, and a link can be consumed once
+CREATE TABLE IF NOT EXISTS magic_link(
+	token_hash TEXT PRIMARY KEY NOT NULL,
+	email TEXT NOT NULL,
+	invite BLOB,
+	created_at TEXT NOT NULL,
+	expires_at TEXT NOT NULL,
+	consumed_at TEXT
+);
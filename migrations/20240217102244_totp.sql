@@ -0,0 +1,19 @@
+-- Add migration script here
+-- `enabled_at` stays unset until the user proves their authenticator works, and
+-- `last_used_step` stops a code being replayed within its time window
+CREATE TABLE IF NOT EXISTS totp(
+	user_id BLOB PRIMARY KEY NOT NULL,
+	secret TEXT NOT NULL,
+	created_at TEXT NOT NULL,
+	enabled_at TEXT,
+	last_used_step INTEGER,
+	FOREIGN KEY(user_id) REFERENCES user(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- Each code is deleted when it's used
+CREATE TABLE IF NOT EXISTS recovery_code(
+	user_id BLOB NOT NULL,
+	code_hash TEXT NOT NULL,
+	PRIMARY KEY (user_id, code_hash),
+	FOREIGN KEY(user_id) REFERENCES user(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
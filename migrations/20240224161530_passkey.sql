@@ -0,0 +1,12 @@
+-- Add migration script here
+-- `id` is the base64url credential id, and `public_key` the COSE key from registration
+CREATE TABLE IF NOT EXISTS passkey(
+	id TEXT PRIMARY KEY NOT NULL,
+	user_id BLOB NOT NULL,
+	name TEXT NOT NULL,
+	public_key BLOB NOT NULL,
+	sign_count INTEGER NOT NULL,
+	created_at TEXT NOT NULL,
+	last_used_at TEXT,
+	FOREIGN KEY(user_id) REFERENCES user(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
@@ -0,0 +1,13 @@
+-- Add migration script here
+-- Only a hash of each token is stored, the token itself is shown once when it's created
+CREATE TABLE IF NOT EXISTS api_token(
+	id BLOB PRIMARY KEY NOT NULL,
+	user_id BLOB NOT NULL,
+	name TEXT NOT NULL,
+	token_hash TEXT NOT NULL UNIQUE,
+	scope TEXT NOT NULL,
+	created_at TEXT NOT NULL,
+	expires_at TEXT,
+	last_used_at TEXT,
+	FOREIGN KEY(user_id) REFERENCES user(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
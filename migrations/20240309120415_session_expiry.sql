@@ -0,0 +1,5 @@
+-- Add migration script here
+-- Expiries used to be written as free-form strings and never checked, so existing
+-- sessions have to go. Everyone logs in again once to pick up a real expiry
+DELETE FROM session;
+CREATE INDEX IF NOT EXISTS session_expiry ON session(expiry);
@@ -0,0 +1,18 @@
+-- Add migration script here
+-- Sessions are now linked to their user so they can be listed and revoked. Old
+-- sessions only carry the user inside their JSON, so everyone logs in again once
+DROP TABLE IF EXISTS session;
+CREATE TABLE session(
+	id TEXT PRIMARY KEY NOT NULL,
+	session TEXT NOT NULL,
+	expiry TEXT,
+	-- Unset for sessions that aren't logged in, e.g. an in-flight OAuth login
+	user_id BLOB,
+	created_at DATETIME NOT NULL,
+	last_seen_at DATETIME NOT NULL,
+	user_agent TEXT,
+	ip_address TEXT,
+	FOREIGN KEY(user_id) REFERENCES user(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+CREATE INDEX IF NOT EXISTS session_expiry ON session(expiry);
+CREATE INDEX IF NOT EXISTS session_user ON session(user_id);
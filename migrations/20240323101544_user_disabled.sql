@@ -0,0 +1,3 @@
+-- Add migration script here
+-- Disabled users can't log in, and any sessions they have stop working
+ALTER TABLE user ADD COLUMN disabled_at DATETIME;
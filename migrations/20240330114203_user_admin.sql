@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE user ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT FALSE;
+-- Someone has to be able to reach the admin area, so the first user to sign up starts as its admin
+UPDATE user SET is_admin = TRUE WHERE id = (SELECT id FROM user ORDER BY created_at LIMIT 1);
@@ -0,0 +1,19 @@
+-- Add migration script here
+-- Invites can now be used more than once and expire. Existing ones keep never expiring
+ALTER TABLE invite ADD COLUMN expires_at DATETIME;
+ALTER TABLE invite ADD COLUMN max_uses INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE invite ADD COLUMN uses INTEGER NOT NULL DEFAULT 0;
+UPDATE invite SET uses = 1 WHERE status = 'Accepted';
+
+-- Everyone who signed up with each invite. `invite.accepted_by` is just the latest
+CREATE TABLE IF NOT EXISTS invite_use(
+	invite_id TEXT NOT NULL,
+	user_id BLOB NOT NULL,
+	accepted_at DATETIME NOT NULL,
+	PRIMARY KEY (invite_id, user_id),
+	FOREIGN KEY(invite_id) REFERENCES invite(id) ON DELETE CASCADE ON UPDATE CASCADE,
+	FOREIGN KEY(user_id) REFERENCES user(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+INSERT INTO invite_use (invite_id, user_id, accepted_at)
+	SELECT invite.id, invite.accepted_by, user.created_at
+	FROM invite JOIN user ON user.id = invite.accepted_by;
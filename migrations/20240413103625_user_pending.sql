@@ -0,0 +1,3 @@
+-- Add migration script here
+-- Set for people who signed up while registrations needed approving, until an admin approves them
+ALTER TABLE user ADD COLUMN pending BOOLEAN NOT NULL DEFAULT FALSE;
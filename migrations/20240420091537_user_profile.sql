@@ -0,0 +1,14 @@
+-- Add migration script here
+-- `email_verified` is only set when a login provider vouched for the address
+ALTER TABLE user ADD COLUMN email TEXT;
+ALTER TABLE user ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT FALSE;
+-- An IANA name like `Europe/London`
+ALTER TABLE user ADD COLUMN timezone TEXT NOT NULL DEFAULT 'UTC';
+ALTER TABLE user ADD COLUMN week_start TEXT NOT NULL DEFAULT 'Monday';
+-- A BCP 47 language tag like `en-GB`
+ALTER TABLE user ADD COLUMN locale TEXT NOT NULL DEFAULT 'en-GB';
+-- Addresses already known from emailed login links were verified by using them
+UPDATE user SET email = (
+	SELECT account.id FROM account WHERE account.user_id = user.id AND account.provider = 'email' LIMIT 1
+);
+UPDATE user SET email_verified = TRUE WHERE email IS NOT NULL;
@@ -0,0 +1,13 @@
+-- Add migration script here
+-- Archived routines leave the home page but keep their history
+ALTER TABLE routine ADD COLUMN archived_at TEXT;
+-- The day the current pause started, if the routine is paused
+ALTER TABLE routine ADD COLUMN paused_since TEXT;
+-- Finished pauses, so their days stay out of streaks once a routine is resumed.
+-- Both days are included
+CREATE TABLE IF NOT EXISTS routine_pause(
+	routine_id BLOB NOT NULL,
+	started_on TEXT NOT NULL,
+	ended_on TEXT NOT NULL,
+	FOREIGN KEY(routine_id) REFERENCES routine(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
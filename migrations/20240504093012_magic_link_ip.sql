@@ -0,0 +1,4 @@
+-- Add migration script here
+-- Where each link was asked for from, so they can be limited per IP address as well as per email
+ALTER TABLE magic_link ADD COLUMN ip_address TEXT;
+CREATE INDEX IF NOT EXISTS magic_link_created_at ON magic_link(created_at);
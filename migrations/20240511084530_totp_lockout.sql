@@ -0,0 +1,4 @@
+-- Add migration script here
+-- Wrong codes in a row across every session, so guesses can't be spread over fresh logins
+ALTER TABLE totp ADD COLUMN failed_attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE totp ADD COLUMN locked_until TEXT;
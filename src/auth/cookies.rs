@@ -0,0 +1,112 @@
+use anyhow::Context;
+use axum::http::HeaderValue;
+use axum_extra::headers;
+use std::time::Duration;
+
+use crate::{error::ApiResult, state::Env};
+
+static SESSION_COOKIE_NAME: &str = "SESSION";
+// Browsers only accept these cookies from a secure origin, for the whole site, and
+// never from a subdomain
+static HOST_SESSION_COOKIE_NAME: &str = "__Host-SESSION";
+
+#[derive(Clone, Copy, Debug)]
+pub struct CookieSettings {
+    // Only send cookies over HTTPS
+    pub secure: bool,
+    // Give the session cookie the `__Host-` prefix
+    pub host_prefix: bool,
+}
+
+impl CookieSettings {
+    pub fn from_env(env: &Env) -> Self {
+        Self {
+            secure: !env.insecure_cookies,
+            host_prefix: env.host_cookie_prefix,
+        }
+    }
+
+    // The prefix requires `Secure`, so it's dropped when that's turned off
+    pub fn session_cookie_name(&self) -> &'static str {
+        if self.secure && self.host_prefix {
+            HOST_SESSION_COOKIE_NAME
+        } else {
+            SESSION_COOKIE_NAME
+        }
+    }
+
+    pub fn session_cookie<'a>(&self, cookies: &'a headers::Cookie) -> Option<&'a str> {
+        cookies.get(self.session_cookie_name())
+    }
+
+    pub fn build<'a>(&self, name: &'a str, value: &'a str) -> CookieBuilder<'a> {
+        CookieBuilder {
+            name,
+            value,
+            path: "/",
+            same_site: "Lax",
+            max_age: None,
+            secure: self.secure,
+        }
+    }
+
+    // The session cookie lasts exactly as long as the session it names
+    pub fn session(&self, value: &str, ttl: Duration) -> ApiResult<HeaderValue> {
+        self.build(self.session_cookie_name(), value)
+            .max_age(ttl)
+            .header()
+    }
+
+    pub fn clear_session(&self) -> ApiResult<HeaderValue> {
+        self.build(self.session_cookie_name(), "").expire().header()
+    }
+}
+
+// Every cookie we set names a session, so none of them are readable from scripts
+pub struct CookieBuilder<'a> {
+    name: &'a str,
+    value: &'a str,
+    path: &'a str,
+    same_site: &'a str,
+    max_age: Option<Duration>,
+    secure: bool,
+}
+
+impl<'a> CookieBuilder<'a> {
+    pub fn path(mut self, path: &'a str) -> Self {
+        self.path = path;
+        self
+    }
+
+    // Leave the cookie off every cross-site request, including top-level navigations
+    pub fn strict(mut self) -> Self {
+        self.same_site = "Strict";
+        self
+    }
+
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    // Tell the browser to delete the cookie straight away
+    pub fn expire(mut self) -> Self {
+        self.value = "";
+        self.max_age = Some(Duration::ZERO);
+        self
+    }
+
+    pub fn header(&self) -> ApiResult<HeaderValue> {
+        let mut cookie = format!(
+            "{}={}; HttpOnly; SameSite={}; Path={}",
+            self.name, self.value, self.same_site, self.path
+        );
+        if self.secure {
+            cookie.push_str("; Secure");
+        }
+        if let Some(max_age) = self.max_age {
+            cookie.push_str(&format!("; Max-Age={}", max_age.as_secs()));
+        }
+        Ok(cookie.parse().context("failed to parse cookie")?)
+    }
+}
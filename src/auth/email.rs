@@ -0,0 +1,209 @@
+use anyhow::Context;
+use axum::{
+    extract::{Query, State},
+    response::{Html, IntoResponse},
+    Form,
+};
+use lettre::Address;
+use rand::RngCore;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::{login_user, sessions::ClientInfo, UserResponse};
+use crate::{
+    database::Database,
+    error::{ApiError, ApiResult},
+    mailer::Email,
+    models::{
+        accounts::{AccountDataLayer, EMAIL_PROVIDER},
+        invites::InviteDataLayer,
+        magic_links::MagicLinkDataLayer,
+        users::{RegistrationMode, UserDataLayer},
+    },
+    state::AppState,
+    templates::login::{confirm_email_login_page, email_sent_page},
+};
+
+const MAGIC_LINK_TTL: Duration = Duration::from_secs(15 * 60);
+// How many links can be out at once for an address, and asked for from an IP address
+const MAX_LINKS_PER_EMAIL: i64 = 3;
+const MAX_LINKS_PER_IP: i64 = 10;
+
+fn require_email_login(state: &AppState<Database>) -> ApiResult<()> {
+    if state.env.email_login {
+        Ok(())
+    } else {
+        Err(ApiError::NotFound)
+    }
+}
+
+// Email addresses are matched case-insensitively, so they're stored lowercased
+pub fn normalize_email(email: &str) -> ApiResult<String> {
+    let email = email.trim().to_lowercase();
+    email
+        .parse::<Address>()
+        .map_err(|_| ApiError::BadRequest("That doesn't look like an email address".to_string()))?;
+    Ok(email)
+}
+
+// Only the hash is stored, so a leaked database can't be used to log in
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[derive(Deserialize)]
+pub struct MagicLinkRequest {
+    email: String,
+    invite: Option<Uuid>,
+}
+
+pub async fn send_magic_link(
+    client: ClientInfo,
+    State(state): State<AppState<Database>>,
+    Form(body): Form<MagicLinkRequest>,
+) -> ApiResult<Html<String>> {
+    require_email_login(&state)?;
+    let email = normalize_email(&body.email)?;
+
+    // Whether a link goes out depends on who has an account, so the rest happens after
+    // responding. Otherwise how long the response took would give that away
+    let page = email_sent_page(&email).into_string();
+    tokio::spawn(async move {
+        if let Err(e) = send_link(&state, email, body.invite, client.ip_address).await {
+            tracing::error!("Failed to send login link: {e:?}");
+        }
+    });
+    Ok(Html(page))
+}
+
+// Links are only sent to existing users, or to new users who'd be let sign up, and only
+// a few at a time to each address and from each IP address
+async fn send_link(
+    state: &AppState<Database>,
+    email: String,
+    invite: Option<Uuid>,
+    ip_address: Option<String>,
+) -> ApiResult<()> {
+    let since = OffsetDateTime::now_utc() - MAGIC_LINK_TTL;
+    let (to_email, from_ip) = state
+        .db
+        .count_magic_links(&email, ip_address.as_deref(), since)
+        .await?;
+    if to_email >= MAX_LINKS_PER_EMAIL || from_ip >= MAX_LINKS_PER_IP {
+        tracing::warn!("Too many login links asked for, not sending one to {email}");
+        return Ok(());
+    }
+
+    let registration = state.registration().await?;
+    // Only an invite that could still be used counts, or anyone could have us email any
+    // address by making one up
+    let invite = match invite {
+        Some(id) => state
+            .db
+            .get_invite(&id)
+            .await?
+            .filter(|invite| invite.is_open())
+            .map(|invite| invite.id),
+        None => None,
+    };
+    let can_sign_up = registration != RegistrationMode::Closed
+        && (invite.is_some() || registration.allows_uninvited());
+    let existing = state.db.get_account(EMAIL_PROVIDER, &email).await?;
+    if existing.is_none() && !can_sign_up {
+        return Ok(());
+    }
+
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token = hex::encode(bytes);
+
+    let expires_at = OffsetDateTime::now_utc() + MAGIC_LINK_TTL;
+    state
+        .db
+        .create_magic_link(
+            &hash_token(&token),
+            &email,
+            invite.as_ref(),
+            ip_address.as_deref(),
+            expires_at,
+        )
+        .await?;
+
+    let link = format!(
+        "{}/login/email/verify?token={token}",
+        state.env.client_url.trim_end_matches('/')
+    );
+    state
+        .mailer
+        .send(Email {
+            to: email,
+            subject: "Your Routines login link".to_string(),
+            body: format!(
+                "Follow this link to log in to Routines:\n\n{link}\n\n\
+                It expires in {} minutes and can only be used once. \
+                If you didn't ask for it, you can ignore this email.",
+                MAGIC_LINK_TTL.as_secs() / 60
+            ),
+        })
+        .await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct MagicLinkToken {
+    token: String,
+}
+
+// Opening the link only shows a confirmation button. Mail scanners that prefetch
+// links would otherwise use it up before the user gets to click it
+pub async fn verify_magic_link(
+    State(state): State<AppState<Database>>,
+    Query(query): Query<MagicLinkToken>,
+) -> ApiResult<Html<String>> {
+    require_email_login(&state)?;
+    Ok(Html(confirm_email_login_page(&query.token).into_string()))
+}
+
+pub async fn email_login(
+    client: ClientInfo,
+    State(state): State<AppState<Database>>,
+    Form(body): Form<MagicLinkToken>,
+) -> ApiResult<impl IntoResponse> {
+    require_email_login(&state)?;
+
+    let link = state
+        .db
+        .consume_magic_link(&hash_token(&body.token))
+        .await?
+        .ok_or_else(|| {
+            ApiError::BadRequest("This login link is invalid or has expired".to_string())
+        })?;
+
+    let name = link
+        .email
+        .split('@')
+        .next()
+        .context("email address without a local part")?
+        .to_string();
+    let user_data = UserResponse {
+        sub: link.email.clone(),
+        email: Some(link.email),
+        // Following the link proves they can read mail sent to it
+        email_verified: true,
+        name,
+    };
+    let user = state
+        .db
+        .upsert_user(
+            EMAIL_PROVIDER,
+            &user_data,
+            link.invite.as_ref(),
+            state.env.registration,
+        )
+        .await?;
+
+    login_user(&state, &user, &client, None).await
+}
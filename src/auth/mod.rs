@@ -0,0 +1,753 @@
+use anyhow::{Context, Result};
+use async_session::{Session, SessionStore};
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts, Path, Query, Request, State},
+    http::{header::SET_COOKIE, HeaderMap},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+    RequestPartsExt,
+};
+use axum_extra::{headers, typed_header::TypedHeaderRejectionReason, TypedHeader};
+use http::{header, request::Parts};
+use openidconnect::{Nonce, PkceCodeVerifier};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{
+    database::{DataLayer, Database},
+    error::{ApiError, ApiResult},
+    models::{
+        accounts::AccountDataLayer,
+        sessions::SessionDataLayer,
+        totp::TotpDataLayer,
+        users::{User, UserDataLayer},
+    },
+    state::{AppState, Env},
+};
+
+pub mod cookies;
+pub mod email;
+pub mod passkey;
+pub mod password;
+pub mod providers;
+pub mod redirect;
+pub mod sessions;
+pub mod tokens;
+pub mod totp;
+
+use cookies::CookieSettings;
+use providers::LEGACY_PROVIDER;
+use redirect::safe_return_to;
+pub use redirect::AuthRedirect;
+use sessions::ClientInfo;
+use tokens::authenticate_token;
+
+// Only the id, the user themselves is loaded fresh for each request
+static USER_KEY: &str = "user_id";
+// Unix timestamp of the login, which the absolute session lifetime counts from
+static LOGGED_IN_AT_KEY: &str = "logged_in_at";
+// Set while a user with TOTP enabled has logged in but not yet entered a code
+static TOTP_PENDING_KEY: &str = "totp_pending";
+// Short-lived session holding the state of an in-flight OAuth login
+static AUTH_COOKIE_NAME: &str = "AUTH_STATE";
+static CSRF_STATE_KEY: &str = "csrf_state";
+static PKCE_VERIFIER_KEY: &str = "pkce_verifier";
+static INVITE_KEY: &str = "invite";
+static NONCE_KEY: &str = "nonce";
+static PROVIDER_KEY: &str = "provider";
+static LINK_USER_KEY: &str = "link_user";
+// Where to send the user once they're logged in
+static RETURN_TO_KEY: &str = "return_to";
+const AUTH_STATE_TTL: Duration = Duration::from_secs(10 * 60);
+// Renewing a session costs a write, so skip it unless it buys at least this much
+const SESSION_RENEWAL_STEP: Duration = Duration::from_secs(60);
+const SESSION_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Clone, Copy, Debug)]
+pub struct SessionLifetime {
+    // Logged out after this long without a request
+    pub idle: Duration,
+    // Logged out this long after logging in, however active the session is
+    pub absolute: Duration,
+}
+
+impl SessionLifetime {
+    pub fn from_env(env: &Env) -> Self {
+        Self {
+            idle: Duration::from_secs(env.session_idle_hours * 60 * 60),
+            absolute: Duration::from_secs(env.session_max_age_days * 24 * 60 * 60),
+        }
+    }
+
+    // How long a session logged in at `logged_in_at` should live from now: a full
+    // idle timeout, capped by the absolute lifetime
+    fn remaining(&self, logged_in_at: i64) -> Duration {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let deadline = logged_in_at.saturating_add(self.absolute.as_secs() as i64);
+        let until_deadline = Duration::from_secs(deadline.saturating_sub(now).max(0) as u64);
+        self.idle.min(until_deadline)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginParams {
+    invite: Option<String>,
+    // Attach this provider to the signed-in user instead of logging in
+    #[serde(default)]
+    link: bool,
+    return_to: Option<String>,
+}
+
+pub async fn provider_login(
+    Path(provider): Path<String>,
+    Query(params): Query<LoginParams>,
+    user: Option<User>,
+    State(state): State<AppState<Database>>,
+) -> ApiResult<impl IntoResponse> {
+    let provider = state.providers.get(&provider).ok_or(ApiError::NotFound)?;
+    let request = provider.authorize_url();
+
+    // Remember the state we sent so the callback can prove it started here, along
+    // with the PKCE verifier that has to accompany the code exchange and the nonce
+    // the ID token must carry
+    let mut session = Session::new();
+    session.expire_in(AUTH_STATE_TTL);
+    session
+        .insert(PROVIDER_KEY, &provider.name)
+        .context("failed in inserting serialized value into session")?;
+    session
+        .insert(CSRF_STATE_KEY, request.csrf_token.secret())
+        .context("failed in inserting serialized value into session")?;
+    session
+        .insert(PKCE_VERIFIER_KEY, request.pkce_verifier.secret())
+        .context("failed in inserting serialized value into session")?;
+    if let Some(nonce) = &request.nonce {
+        session
+            .insert(NONCE_KEY, nonce.secret())
+            .context("failed in inserting serialized value into session")?;
+    }
+    // Carry the invite through so a new user can be registered on return
+    if let Some(invite) = params.invite.and_then(|i| Uuid::parse_str(&i).ok()) {
+        session
+            .insert(INVITE_KEY, invite)
+            .context("failed in inserting serialized value into session")?;
+    }
+    if let Some(return_to) = params.return_to.as_deref().and_then(safe_return_to) {
+        session
+            .insert(RETURN_TO_KEY, return_to)
+            .context("failed in inserting serialized value into session")?;
+    }
+    if params.link {
+        let user = user.ok_or_else(|| {
+            ApiError::BadRequest("You need to be logged in to link a login".to_string())
+        })?;
+        session
+            .insert(LINK_USER_KEY, user.id)
+            .context("failed in inserting serialized value into session")?;
+    }
+
+    let store = &state.session_store;
+    let cookie = store
+        .store_session(session)
+        .await
+        .context("failed to store session")?
+        .context("unexpected error retrieving cookie value")?;
+    let cookie = store
+        .cookies
+        .build(AUTH_COOKIE_NAME, &cookie)
+        .path("/auth")
+        .max_age(AUTH_STATE_TTL)
+        .header()?;
+
+    let mut headers = HeaderMap::new();
+    headers.insert(SET_COOKIE, cookie);
+
+    // Redirect to the provider's authorization endpoint
+    Ok((headers, Redirect::to(request.url.as_ref())))
+}
+
+// Identity asserted by the provider
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserResponse {
+    pub sub: String, // The ID
+    pub email: Option<String>,
+    // Whether the provider checked the user owns `email`
+    pub email_verified: bool,
+    pub name: String,
+}
+
+// Valid user session required. If there is none, redirect to the auth page
+pub async fn protected(user: User) -> impl IntoResponse {
+    format!(
+        "Welcome to the protected area :)\nHere's your info:\n{:?}",
+        user.id
+    )
+}
+
+pub async fn logout<T: for<'a> DataLayer<'a>>(
+    State(store): State<DBSessionStore<T>>,
+    cookies: Option<TypedHeader<headers::Cookie>>,
+) -> ApiResult<impl IntoResponse> {
+    let cookie = cookies
+        .as_ref()
+        .and_then(|TypedHeader(cookies)| store.cookies.session_cookie(cookies));
+    let session = match cookie {
+        Some(cookie) => store
+            .load_session(cookie.to_string())
+            .await
+            .context("failed to load session")?,
+        None => None,
+    };
+    if let Some(session) = session {
+        store
+            .destroy_session(session)
+            .await
+            .context("failed to destroy session")?;
+    }
+
+    // Clear the cookie whether or not its session was still around
+    let mut headers = HeaderMap::new();
+    headers.insert(SET_COOKIE, store.cookies.clear_session()?);
+    Ok((headers, Redirect::to("/")))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuthRequest {
+    code: String,
+    state: String,
+}
+
+fn invalid_auth_state() -> ApiError {
+    ApiError::BadRequest("Login request could not be verified".to_string())
+}
+
+// Load and consume the pre-auth session created by `provider_login`, checking the
+// returned state matches the one we generated for this provider
+async fn take_auth_state(
+    store: &DBSessionStore<Database>,
+    cookies: Option<TypedHeader<headers::Cookie>>,
+    provider: &str,
+    returned_state: &str,
+) -> ApiResult<Session> {
+    let cookie = cookies
+        .as_ref()
+        .and_then(|TypedHeader(cookies)| cookies.get(AUTH_COOKIE_NAME))
+        .ok_or_else(invalid_auth_state)?;
+    let session = store
+        .load_session(cookie.to_string())
+        .await
+        .context("failed to load session")?
+        .ok_or_else(invalid_auth_state)?;
+
+    // The state is single-use, so drop it before checking anything else
+    store
+        .destroy_session(session.clone())
+        .await
+        .context("failed to destroy session")?;
+
+    if session.is_expired() || session.get::<String>(PROVIDER_KEY).as_deref() != Some(provider) {
+        return Err(invalid_auth_state());
+    }
+    match session.get::<String>(CSRF_STATE_KEY) {
+        Some(expected) if expected == returned_state => Ok(session),
+        _ => Err(invalid_auth_state()),
+    }
+}
+
+pub async fn login_authorized(
+    Path(provider): Path<String>,
+    Query(query): Query<AuthRequest>,
+    user: Option<User>,
+    client: ClientInfo,
+    State(state): State<AppState<Database>>,
+    cookies: Option<TypedHeader<headers::Cookie>>,
+) -> ApiResult<Response> {
+    let provider = state.providers.get(&provider).ok_or(ApiError::NotFound)?;
+    let auth_state =
+        take_auth_state(&state.session_store, cookies, &provider.name, &query.state).await?;
+    let pkce_verifier = auth_state
+        .get::<String>(PKCE_VERIFIER_KEY)
+        .map(PkceCodeVerifier::new)
+        .ok_or_else(invalid_auth_state)?;
+    let nonce = auth_state.get::<String>(NONCE_KEY).map(Nonce::new);
+
+    let user_data = provider
+        .exchange_code(query.code, pkce_verifier, nonce, &state.http_client)
+        .await?;
+
+    if let Some(link_user) = auth_state.get::<Uuid>(LINK_USER_KEY) {
+        // The browser that started linking must still be logged in as the same user
+        if user.map(|u| u.id) != Some(link_user) {
+            return Err(invalid_auth_state());
+        }
+        state
+            .db
+            .link_account(&link_user, &provider.name, &user_data.sub)
+            .await?;
+        let headers = clear_auth_state_cookie(&state.session_store)?;
+        return Ok((headers, Redirect::to("/settings")).into_response());
+    }
+
+    let invite = auth_state.get::<Uuid>(INVITE_KEY);
+    let user = state
+        .db
+        .upsert_user(
+            &provider.name,
+            &user_data,
+            invite.as_ref(),
+            state.env.registration,
+        )
+        .await?;
+
+    // Set the session cookie, clearing the spent pre-auth cookie
+    let mut headers = clear_auth_state_cookie(&state.session_store)?;
+    let return_to = auth_state.get::<String>(RETURN_TO_KEY);
+    let (session_headers, redirect) =
+        login_user(&state, &user, &client, return_to.as_deref()).await?;
+    append_headers(&mut headers, session_headers);
+
+    Ok((headers, redirect).into_response())
+}
+
+// The callback from before providers were configurable, still registered with some
+// Google clients through `REDIRECT_URL`
+pub async fn legacy_login_authorized(
+    query: Query<AuthRequest>,
+    user: Option<User>,
+    client: ClientInfo,
+    state: State<AppState<Database>>,
+    cookies: Option<TypedHeader<headers::Cookie>>,
+) -> ApiResult<Response> {
+    let provider = Path(LEGACY_PROVIDER.to_string());
+    login_authorized(provider, query, user, client, state, cookies).await
+}
+
+// Log in a user who has passed their primary login, sending them on to `return_to`.
+// If they've turned on TOTP the session stays pending, and they're sent on to enter
+// a code first
+pub async fn login_user(
+    state: &AppState<Database>,
+    user: &User,
+    client: &ClientInfo,
+    return_to: Option<&str>,
+) -> ApiResult<(HeaderMap, Redirect)> {
+    if user.is_disabled() {
+        return Err(disabled_user());
+    }
+    if user.pending {
+        return Err(ApiError::Forbidden(
+            "Thanks for signing up! An admin needs to approve your account before you can log in"
+                .to_string(),
+        ));
+    }
+    let return_to = return_to.and_then(safe_return_to);
+    let totp_pending = state
+        .db
+        .get_totp(&user.id)
+        .await?
+        .is_some_and(|totp| totp.is_enabled());
+    let pending_return_to = return_to.filter(|_| totp_pending);
+    let headers = start_session(
+        &state.session_store,
+        user,
+        client,
+        totp_pending,
+        pending_return_to,
+    )
+    .await?;
+    let redirect = match return_to {
+        _ if totp_pending => "/login/totp",
+        Some(return_to) => return_to,
+        None => "/",
+    };
+    Ok((headers, Redirect::to(redirect)))
+}
+
+// Start a new session, returning the headers that set its cookie. Any session the
+// browser already had is ended, so every login gets a fresh id
+async fn start_session(
+    store: &DBSessionStore<Database>,
+    user: &User,
+    client: &ClientInfo,
+    totp_pending: bool,
+    // Held on to until the TOTP step is done
+    return_to: Option<&str>,
+) -> ApiResult<HeaderMap> {
+    // Create a new session filled with user data
+    let mut session = Session::new();
+    let logged_in_at = OffsetDateTime::now_utc().unix_timestamp();
+    session
+        .insert(USER_KEY, user.id)
+        .context("failed in inserting serialized value into session")?;
+    session
+        .insert(LOGGED_IN_AT_KEY, logged_in_at)
+        .context("failed in inserting serialized value into session")?;
+    let ttl = store.lifetime.remaining(logged_in_at);
+    session.expire_in(ttl);
+    if totp_pending {
+        session
+            .insert(TOTP_PENDING_KEY, true)
+            .context("failed in inserting serialized value into session")?;
+    }
+    if let Some(return_to) = return_to {
+        session
+            .insert(RETURN_TO_KEY, return_to)
+            .context("failed in inserting serialized value into session")?;
+    }
+
+    // Store session and get corresponding cookie, noting where it was started from
+    let id = session.id().to_string();
+    let cookie = store
+        .store_session(session)
+        .await
+        .context("failed to store session")?
+        .context("unexpected error retrieving cookie value")?;
+    store
+        .db
+        .set_session_client(
+            &id,
+            client.user_agent.as_deref(),
+            client.ip_address.as_deref(),
+        )
+        .await?;
+
+    if let Some(previous) = &client.session_id {
+        store.db.delete_session(previous).await?;
+    }
+
+    let mut headers = HeaderMap::new();
+    headers.insert(SET_COOKIE, store.cookies.session(&cookie, ttl)?);
+    Ok(headers)
+}
+
+// Give the request's session a new id, e.g. after the user changes how they log in,
+// so a copy of the old cookie stops working. Requests authenticated some other way
+// have nothing to rotate
+pub async fn rotate_session(
+    store: &DBSessionStore<Database>,
+    cookies: Option<&headers::Cookie>,
+) -> ApiResult<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    let Some(cookie) = cookies.and_then(|cookies| store.cookies.session_cookie(cookies)) else {
+        return Ok(headers);
+    };
+    let Some(mut session) = store
+        .load_session(cookie.to_string())
+        .await
+        .context("failed to load session")?
+    else {
+        return Ok(headers);
+    };
+
+    let previous = session.id().to_string();
+    session.regenerate();
+    // Moving the row keeps the session's place in the active sessions list
+    store.db.rename_session(&previous, session.id()).await?;
+    let ttl = session.expires_in().unwrap_or_default();
+    let cookie = store
+        .store_session(session)
+        .await
+        .context("failed to store session")?
+        .context("unexpected error retrieving cookie value")?;
+    headers.insert(SET_COOKIE, store.cookies.session(&cookie, ttl)?);
+    Ok(headers)
+}
+
+// Slide the expiry of the request's session forward, so active users stay logged in
+// until they reach the absolute lifetime
+pub async fn renew_session(
+    State(store): State<DBSessionStore<Database>>,
+    cookies: Option<TypedHeader<headers::Cookie>>,
+    request: Request,
+    next: Next,
+) -> ApiResult<Response> {
+    let cookie = cookies
+        .as_ref()
+        .and_then(|TypedHeader(cookies)| store.cookies.session_cookie(cookies))
+        .map(str::to_string);
+    // Renewal is best-effort, a malformed cookie is rejected by the extractors later
+    let session = match &cookie {
+        Some(cookie) => store.load_session(cookie.clone()).await.ok().flatten(),
+        None => None,
+    };
+
+    let mut renewed = None;
+    if let (Some(cookie), Some(mut session)) = (cookie, session) {
+        if let Some(logged_in_at) = session.get::<i64>(LOGGED_IN_AT_KEY) {
+            let ttl = store.lifetime.remaining(logged_in_at);
+            let current = session.expires_in().unwrap_or_default();
+            if ttl.saturating_sub(current) >= SESSION_RENEWAL_STEP {
+                session.expire_in(ttl);
+                store
+                    .store_session(session)
+                    .await
+                    .context("failed to store session")?;
+                // The expiry isn't part of the cookie value, so the same one is sent back
+                renewed = Some(store.cookies.session(&cookie, ttl)?);
+            }
+        }
+    }
+
+    let mut response = next.run(request).await;
+    // Leave it alone if the handler has just logged in or out
+    let prefix = format!("{}=", store.cookies.session_cookie_name());
+    let sets_session = response
+        .headers()
+        .get_all(SET_COOKIE)
+        .iter()
+        .any(|value| value.as_bytes().starts_with(prefix.as_bytes()));
+    if let Some(renewed) = renewed.filter(|_| !sets_session) {
+        response.headers_mut().append(SET_COOKIE, renewed);
+    }
+    Ok(response)
+}
+
+// Expired sessions are already ignored when loading, this just keeps the table small
+pub async fn purge_expired_sessions(db: Database) {
+    let mut interval = tokio::time::interval(SESSION_PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        match db.delete_expired_sessions().await {
+            Ok(purged) => tracing::debug!("Purged {purged} expired sessions"),
+            Err(e) => tracing::error!("Failed to purge expired sessions: {e:?}"),
+        }
+    }
+}
+
+fn clear_auth_state_cookie(store: &DBSessionStore<Database>) -> ApiResult<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        SET_COOKIE,
+        store
+            .cookies
+            .build(AUTH_COOKIE_NAME, "")
+            .path("/auth")
+            .expire()
+            .header()?,
+    );
+    Ok(headers)
+}
+
+// Add every value in `more` to `headers`. `HeaderMap::extend` replaces values that are
+// already there, which would drop a `Set-Cookie` clearing another cookie
+pub fn append_headers(headers: &mut HeaderMap, more: HeaderMap) {
+    let mut name = None;
+    for (key, value) in more {
+        // Only the first value of each header comes with its name
+        if key.is_some() {
+            name = key;
+        }
+        if let Some(name) = &name {
+            headers.append(name, value);
+        }
+    }
+}
+
+pub fn disabled_user() -> ApiError {
+    ApiError::Forbidden("This account has been disabled".to_string())
+}
+
+// Load the current version of the user a session belongs to. Sessions of users who
+// have since been deleted or disabled are ended
+async fn session_user(
+    store: &DBSessionStore<Database>,
+    session: &Session,
+) -> ApiResult<Option<User>> {
+    // Sessions from before only the id was kept don't have one, so need logging in again
+    let Some(user_id) = session.get::<Uuid>(USER_KEY) else {
+        return Ok(None);
+    };
+    match store.db.get_user(&user_id).await? {
+        Some(user) if !user.is_disabled() && !user.pending => Ok(Some(user)),
+        _ => {
+            store
+                .destroy_session(session.clone())
+                .await
+                .context("failed to destroy session")?;
+            Ok(None)
+        }
+    }
+}
+
+// Load the session named by the request's session cookie. Without one, the user is
+// sent to log in
+async fn request_session(
+    parts: &mut Parts,
+    store: &DBSessionStore<Database>,
+) -> Result<Session, Response> {
+    let cookies = parts.extract::<TypedHeader<headers::Cookie>>().await;
+    let redirect = || AuthRedirect::for_request(parts).into_response();
+    let cookies = match cookies {
+        Ok(TypedHeader(cookies)) => cookies,
+        Err(e) => {
+            // A header we can't read can't name a session either
+            if !matches!(e.reason(), TypedHeaderRejectionReason::Missing) {
+                tracing::warn!("Unreadable Cookie header: {e}");
+            }
+            return Err(redirect());
+        }
+    };
+    let session_cookie = store
+        .cookies
+        .session_cookie(&cookies)
+        .ok_or_else(redirect)?;
+
+    let session = store
+        .load_session(session_cookie.to_string())
+        .await
+        .context("failed to load session")
+        .map_err(|e| ApiError::from(e).into_response())?
+        .ok_or_else(redirect)?;
+    Ok(session)
+}
+
+fn bearer_token(parts: &Parts) -> Option<&str> {
+    parts
+        .headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for User
+where
+    DBSessionStore<Database>: FromRef<S>,
+    S: Send + Sync,
+{
+    // Scripts get an error for a bad API token. Otherwise, if anything goes wrong or
+    // no session is found, redirect to the auth page
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(token) = bearer_token(parts) {
+            let store = DBSessionStore::from_ref(state);
+            return authenticate_token(&store.db, token, &parts.method)
+                .await
+                .map_err(IntoResponse::into_response);
+        }
+        let SessionUser(user) = SessionUser::from_request_parts(parts, state).await?;
+        Ok(user)
+    }
+}
+
+// A user logged in through the browser. Managing the account itself, and the admin area,
+// need one of these, so an API token can't be used to mint more tokens or lock its
+// owner out
+pub struct SessionUser(pub User);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for SessionUser
+where
+    DBSessionStore<Database>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if bearer_token(parts).is_some() {
+            return Err(
+                ApiError::Forbidden("API tokens can't be used for this".to_string())
+                    .into_response(),
+            );
+        }
+        let store = DBSessionStore::from_ref(state);
+        let session = request_session(parts, &store).await?;
+
+        // Half logged in sessions can only be used to finish the TOTP step
+        if session.get::<bool>(TOTP_PENDING_KEY).unwrap_or(false) {
+            return Err(AuthRedirect::for_request(parts).into_response());
+        }
+        let user = session_user(&store, &session)
+            .await
+            .map_err(IntoResponse::into_response)?
+            .ok_or_else(|| AuthRedirect::for_request(parts).into_response())?;
+
+        Ok(Self(user))
+    }
+}
+
+// A logged in user who administers this instance
+pub struct Admin(pub User);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Admin
+where
+    DBSessionStore<Database>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let SessionUser(user) = SessionUser::from_request_parts(parts, state).await?;
+        if !user.is_admin {
+            return Err(
+                ApiError::Forbidden("Only administrators can do this".to_string()).into_response(),
+            );
+        }
+        Ok(Self(user))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DBSessionStore<T: for<'a> DataLayer<'a>> {
+    pub db: T,
+    pub lifetime: SessionLifetime,
+    pub cookies: CookieSettings,
+}
+
+impl<T: for<'a> DataLayer<'a>> DBSessionStore<T> {
+    // Id of the session named by the session cookie, without loading it
+    pub fn session_id(&self, cookies: &headers::Cookie) -> Option<String> {
+        let cookie = self.cookies.session_cookie(cookies)?;
+        Session::id_from_cookie_value(cookie).ok()
+    }
+}
+
+#[async_trait]
+impl<T: for<'a> DataLayer<'a>> SessionStore for DBSessionStore<T> {
+    async fn load_session(
+        &self,
+        cookie_value: String,
+    ) -> Result<Option<Session>, async_session::Error> {
+        let id = Session::id_from_cookie_value(&cookie_value)?;
+        let Some(session_str) = self.db.get_session(&id).await? else {
+            return Ok(None);
+        };
+        serde_json::from_str(&session_str).context("")
+    }
+
+    async fn store_session(&self, session: Session) -> Result<Option<String>> {
+        let id = session.id();
+        let serialized_session = serde_json::to_string(&session)?;
+        let user_id = session.get::<Uuid>(USER_KEY);
+
+        self.db
+            .insert_session(
+                id,
+                &serialized_session,
+                user_id.as_ref(),
+                session
+                    .expiry()
+                    .and_then(|t| OffsetDateTime::from_unix_timestamp(t.timestamp()).ok()),
+            )
+            .await?;
+
+        Ok(session.into_cookie_value())
+    }
+
+    async fn destroy_session(&self, session: Session) -> Result<()> {
+        let id = session.id();
+        self.db.delete_session(id).await?;
+        Ok(())
+    }
+
+    async fn clear_store(&self) -> Result<()> {
+        self.db.delete_all_sessions().await
+    }
+}
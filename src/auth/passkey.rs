@@ -0,0 +1,727 @@
+use anyhow::Context;
+use async_session::{Session, SessionStore};
+use axum::{
+    extract::State,
+    http::{header::SET_COOKIE, HeaderMap},
+    response::{Html, IntoResponse},
+    Form, Json,
+};
+use axum_extra::{headers, TypedHeader};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ciborium::Value;
+use p256::ecdsa::{self, signature::Verifier};
+use rand::RngCore;
+use reqwest::Url;
+use rsa::{pkcs1v15, BigUint, RsaPublicKey};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use uuid::Uuid;
+
+use super::{append_headers, login_user, sessions::ClientInfo, DBSessionStore, SessionUser};
+use crate::{
+    database::Database,
+    error::{ApiError, ApiResult},
+    models::{passkeys::PasskeyDataLayer, sessions::SessionDataLayer, users::UserDataLayer},
+    state::AppState,
+    templates::settings::passkeys_card,
+};
+
+// Short-lived session holding the challenge of an in-flight passkey ceremony
+static PASSKEY_COOKIE_NAME: &str = "PASSKEY_STATE";
+static CHALLENGE_KEY: &str = "challenge";
+// Set when the ceremony registers a new passkey for this user, rather than logging in
+static REGISTER_USER_KEY: &str = "register_user";
+const CEREMONY_TTL: Duration = Duration::from_secs(5 * 60);
+// Anyone can start logging in, so each IP address can only have a few logins going at once
+const MAX_LOGIN_CEREMONIES_PER_IP: i64 = 10;
+
+// COSE key parameters and values, see https://www.iana.org/assignments/cose/cose.xhtml
+const COSE_KEY_KTY: i64 = 1;
+const COSE_KEY_ALG: i64 = 3;
+const COSE_KTY_EC2: i64 = 2;
+const COSE_KTY_RSA: i64 = 3;
+const COSE_ALG_ES256: i64 = -7;
+const COSE_ALG_RS256: i64 = -257;
+// EC2 keys are a curve and a point, RSA keys a modulus and exponent
+const COSE_EC2_CRV: i64 = -1;
+const COSE_EC2_X: i64 = -2;
+const COSE_EC2_Y: i64 = -3;
+const COSE_CRV_P256: i64 = 1;
+const COSE_RSA_N: i64 = -1;
+const COSE_RSA_E: i64 = -2;
+// Anything shorter than 2048 bits is too weak to trust
+const MIN_RSA_MODULUS_BYTES: usize = 256;
+
+// Authenticator data flags
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+const MAX_NAME_LENGTH: usize = 64;
+
+fn invalid_passkey() -> ApiError {
+    ApiError::BadRequest("Passkey could not be verified".to_string())
+}
+
+fn decode(value: &str) -> ApiResult<Vec<u8>> {
+    URL_SAFE_NO_PAD.decode(value).map_err(|_| invalid_passkey())
+}
+
+// Passkeys are bound to our domain, and every response has to come from our origin
+struct RelyingParty {
+    id: String,
+    origin: String,
+}
+
+impl RelyingParty {
+    fn from_client_url(client_url: &str) -> ApiResult<Self> {
+        let url = Url::parse(client_url).context("failed to parse client URL")?;
+        Ok(Self {
+            id: url
+                .host_str()
+                .context("client URL has no host")?
+                .to_string(),
+            origin: url.origin().ascii_serialization(),
+        })
+    }
+}
+
+// Remember a fresh challenge until the browser comes back with the signed response
+async fn start_ceremony(
+    store: &DBSessionStore<Database>,
+    register_user: Option<&Uuid>,
+    client: &ClientInfo,
+) -> ApiResult<(String, HeaderMap)> {
+    let mut challenge = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut challenge);
+    let challenge = URL_SAFE_NO_PAD.encode(challenge);
+
+    let mut session = Session::new();
+    session.expire_in(CEREMONY_TTL);
+    session
+        .insert(CHALLENGE_KEY, &challenge)
+        .context("failed in inserting serialized value into session")?;
+    if let Some(user_id) = register_user {
+        session
+            .insert(REGISTER_USER_KEY, user_id)
+            .context("failed in inserting serialized value into session")?;
+    }
+
+    let id = session.id().to_string();
+    let cookie = store
+        .store_session(session)
+        .await
+        .context("failed to store session")?
+        .context("unexpected error retrieving cookie value")?;
+    // Kept so login ceremonies can be counted per IP address
+    store
+        .db
+        .set_session_client(
+            &id,
+            client.user_agent.as_deref(),
+            client.ip_address.as_deref(),
+        )
+        .await?;
+    let cookie = store
+        .cookies
+        .build(PASSKEY_COOKIE_NAME, &cookie)
+        .strict()
+        .max_age(CEREMONY_TTL)
+        .header()?;
+
+    let mut headers = HeaderMap::new();
+    headers.insert(SET_COOKIE, cookie);
+    Ok((challenge, headers))
+}
+
+// Load and consume the ceremony session, returning the challenge it was started with
+async fn take_ceremony(
+    store: &DBSessionStore<Database>,
+    cookies: Option<TypedHeader<headers::Cookie>>,
+    register_user: Option<&Uuid>,
+) -> ApiResult<String> {
+    let cookie = cookies
+        .as_ref()
+        .and_then(|TypedHeader(cookies)| cookies.get(PASSKEY_COOKIE_NAME))
+        .ok_or_else(invalid_passkey)?;
+    let session = store
+        .load_session(cookie.to_string())
+        .await
+        .context("failed to load session")?
+        .ok_or_else(invalid_passkey)?;
+
+    // Each challenge can only be answered once
+    store
+        .destroy_session(session.clone())
+        .await
+        .context("failed to destroy session")?;
+
+    if session.is_expired() || session.get::<Uuid>(REGISTER_USER_KEY).as_ref() != register_user {
+        return Err(invalid_passkey());
+    }
+    session
+        .get::<String>(CHALLENGE_KEY)
+        .ok_or_else(invalid_passkey)
+}
+
+fn clear_ceremony_cookie(store: &DBSessionStore<Database>) -> ApiResult<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        SET_COOKIE,
+        store
+            .cookies
+            .build(PASSKEY_COOKIE_NAME, "")
+            .strict()
+            .expire()
+            .header()?,
+    );
+    Ok(headers)
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+    #[serde(rename = "crossOrigin", default)]
+    cross_origin: bool,
+}
+
+// The browser's record of what it was asked to sign, and where
+fn verify_client_data(
+    client_data: &[u8],
+    kind: &str,
+    challenge: &str,
+    rp: &RelyingParty,
+) -> ApiResult<()> {
+    let client_data: ClientData =
+        serde_json::from_slice(client_data).map_err(|_| invalid_passkey())?;
+    if client_data.kind != kind
+        || client_data.challenge != challenge
+        || client_data.origin != rp.origin
+        || client_data.cross_origin
+    {
+        return Err(invalid_passkey());
+    }
+    Ok(())
+}
+
+struct AuthenticatorData {
+    sign_count: u32,
+    // Only present when registering
+    credential: Option<AttestedCredential>,
+}
+
+struct AttestedCredential {
+    id: Vec<u8>,
+    public_key: Vec<u8>,
+}
+
+// Layout: rpIdHash (32) | flags (1) | signCount (4) | aaguid (16) | credentialIdLength (2)
+// | credentialId | credentialPublicKey (COSE), where the last four are only present when
+// the attested credential flag is set
+fn parse_authenticator_data(data: &[u8], rp: &RelyingParty) -> ApiResult<AuthenticatorData> {
+    if data.len() < 37 || data[..32] != Sha256::digest(rp.id.as_bytes())[..] {
+        return Err(invalid_passkey());
+    }
+    let flags = data[32];
+    // Passkeys replace passwords, so the authenticator must have checked it's really the user
+    if flags & FLAG_USER_PRESENT == 0 || flags & FLAG_USER_VERIFIED == 0 {
+        return Err(invalid_passkey());
+    }
+    let sign_count = u32::from_be_bytes([data[33], data[34], data[35], data[36]]);
+
+    let credential = if flags & FLAG_ATTESTED_CREDENTIAL != 0 {
+        let rest = data.get(53..).ok_or_else(invalid_passkey)?;
+        let (length, rest) = rest.split_at_checked(2).ok_or_else(invalid_passkey)?;
+        let length = u16::from_be_bytes([length[0], length[1]]) as usize;
+        let (id, rest) = rest.split_at_checked(length).ok_or_else(invalid_passkey)?;
+
+        // The key is followed by any extensions, so read exactly one CBOR item
+        let mut reader = rest;
+        let _: Value = ciborium::from_reader(&mut reader).map_err(|_| invalid_passkey())?;
+        let public_key = rest[..rest.len() - reader.len()].to_vec();
+        Some(AttestedCredential {
+            id: id.to_vec(),
+            public_key,
+        })
+    } else {
+        None
+    };
+
+    Ok(AuthenticatorData {
+        sign_count,
+        credential,
+    })
+}
+
+enum PublicKey {
+    Es256(ecdsa::VerifyingKey),
+    Rs256(pkcs1v15::VerifyingKey<Sha256>),
+}
+
+impl PublicKey {
+    // The key type has to match the algorithm, so a key can't be read as something
+    // other than what the authenticator made
+    fn from_cose(bytes: &[u8]) -> ApiResult<Self> {
+        let Value::Map(entries) = ciborium::from_reader(bytes).map_err(|_| invalid_passkey())?
+        else {
+            return Err(invalid_passkey());
+        };
+        let get = |label: i64| {
+            entries
+                .iter()
+                .find(|(key, _)| {
+                    key.as_integer().and_then(|key| i64::try_from(key).ok()) == Some(label)
+                })
+                .map(|(_, value)| value)
+        };
+        let integer = |label: i64| {
+            get(label)
+                .and_then(|value| value.as_integer())
+                .and_then(|value| i64::try_from(value).ok())
+                .ok_or_else(invalid_passkey)
+        };
+        let bytes = |label: i64| {
+            get(label)
+                .and_then(|value| value.as_bytes())
+                .ok_or_else(invalid_passkey)
+        };
+
+        match (integer(COSE_KEY_ALG)?, integer(COSE_KEY_KTY)?) {
+            (COSE_ALG_ES256, COSE_KTY_EC2) => {
+                if integer(COSE_EC2_CRV)? != COSE_CRV_P256 {
+                    return Err(invalid_passkey());
+                }
+                let (x, y) = (bytes(COSE_EC2_X)?, bytes(COSE_EC2_Y)?);
+                if x.len() != 32 || y.len() != 32 {
+                    return Err(invalid_passkey());
+                }
+                let mut point = vec![0x04];
+                point.extend_from_slice(x);
+                point.extend_from_slice(y);
+                let key =
+                    ecdsa::VerifyingKey::from_sec1_bytes(&point).map_err(|_| invalid_passkey())?;
+                Ok(Self::Es256(key))
+            }
+            (COSE_ALG_RS256, COSE_KTY_RSA) => {
+                let n = bytes(COSE_RSA_N)?;
+                let n = &n[n.iter().take_while(|byte| **byte == 0).count()..];
+                if n.len() < MIN_RSA_MODULUS_BYTES {
+                    return Err(invalid_passkey());
+                }
+                let key = RsaPublicKey::new(
+                    BigUint::from_bytes_be(n),
+                    BigUint::from_bytes_be(bytes(COSE_RSA_E)?),
+                )
+                .map_err(|_| invalid_passkey())?;
+                Ok(Self::Rs256(pkcs1v15::VerifyingKey::new(key)))
+            }
+            (COSE_ALG_ES256 | COSE_ALG_RS256, _) => Err(invalid_passkey()),
+            _ => Err(ApiError::BadRequest(
+                "This passkey uses an algorithm we don't support".to_string(),
+            )),
+        }
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        match self {
+            Self::Es256(key) => ecdsa::DerSignature::try_from(signature)
+                .is_ok_and(|signature| key.verify(message, &signature).is_ok()),
+            Self::Rs256(key) => pkcs1v15::Signature::try_from(signature)
+                .is_ok_and(|signature| key.verify(message, &signature).is_ok()),
+        }
+    }
+}
+
+pub async fn registration_options(
+    SessionUser(user): SessionUser,
+    client: ClientInfo,
+    State(state): State<AppState<Database>>,
+) -> ApiResult<impl IntoResponse> {
+    let rp = RelyingParty::from_client_url(&state.env.client_url)?;
+    let existing = state.db.get_passkeys(&user.id).await?;
+    let (challenge, headers) =
+        start_ceremony(&state.session_store, Some(&user.id), &client).await?;
+
+    // Passed to `navigator.credentials.create`, once static/js/passkey.js decodes the binary fields
+    let options = json!({
+        "challenge": challenge,
+        "rp": { "id": rp.id, "name": "Routines" },
+        "user": {
+            "id": URL_SAFE_NO_PAD.encode(user.id.as_bytes()),
+            "name": user.name,
+            "displayName": user.name,
+        },
+        "pubKeyCredParams": [
+            { "type": "public-key", "alg": COSE_ALG_ES256 },
+            { "type": "public-key", "alg": COSE_ALG_RS256 },
+        ],
+        "excludeCredentials": existing
+            .iter()
+            .map(|passkey| json!({ "type": "public-key", "id": passkey.id }))
+            .collect::<Vec<_>>(),
+        "authenticatorSelection": {
+            "residentKey": "required",
+            "userVerification": "required",
+        },
+        "attestation": "none",
+        "timeout": CEREMONY_TTL.as_millis() as u64,
+    });
+    Ok((headers, Json(options)))
+}
+
+#[derive(Deserialize)]
+pub struct RegistrationRequest {
+    name: String,
+    id: String,
+    client_data: String,
+    attestation_object: String,
+}
+
+pub async fn register_passkey(
+    SessionUser(user): SessionUser,
+    State(state): State<AppState<Database>>,
+    cookies: Option<TypedHeader<headers::Cookie>>,
+    Json(body): Json<RegistrationRequest>,
+) -> ApiResult<impl IntoResponse> {
+    let challenge = take_ceremony(&state.session_store, cookies, Some(&user.id)).await?;
+    let rp = RelyingParty::from_client_url(&state.env.client_url)?;
+    verify_client_data(
+        &decode(&body.client_data)?,
+        "webauthn.create",
+        &challenge,
+        &rp,
+    )?;
+
+    // We ask for no attestation, so only the authenticator data matters
+    let attestation: Value = ciborium::from_reader(decode(&body.attestation_object)?.as_slice())
+        .map_err(|_| invalid_passkey())?;
+    let auth_data = attestation
+        .as_map()
+        .and_then(|entries| {
+            entries
+                .iter()
+                .find(|(key, _)| key.as_text() == Some("authData"))
+        })
+        .and_then(|(_, value)| value.as_bytes())
+        .ok_or_else(invalid_passkey)?;
+    let auth_data = parse_authenticator_data(auth_data, &rp)?;
+    let credential = auth_data.credential.ok_or_else(invalid_passkey)?;
+    let id = URL_SAFE_NO_PAD.encode(&credential.id);
+    if id != body.id {
+        return Err(invalid_passkey());
+    }
+    // Make sure we'll be able to check its signatures before saving it
+    PublicKey::from_cose(&credential.public_key)?;
+
+    let name = passkey_name(&body.name)?;
+    state
+        .db
+        .add_passkey(
+            &id,
+            &user.id,
+            &name,
+            &credential.public_key,
+            auth_data.sign_count.into(),
+        )
+        .await?;
+
+    let passkeys = state.db.get_passkeys(&user.id).await?;
+    let markup = passkeys_card(&passkeys, Some("Passkey added"));
+    let headers = clear_ceremony_cookie(&state.session_store)?;
+    Ok((headers, Html(markup.into_string())))
+}
+
+fn passkey_name(name: &str) -> ApiResult<String> {
+    match name.trim() {
+        "" => Ok("Passkey".to_string()),
+        name if name.chars().count() > MAX_NAME_LENGTH => Err(ApiError::BadRequest(format!(
+            "Passkey names can be at most {MAX_NAME_LENGTH} characters"
+        ))),
+        name => Ok(name.to_string()),
+    }
+}
+
+// Every call stores a ceremony session until it's answered or expires, and is purged with
+// the other expired sessions
+pub async fn login_options(
+    client: ClientInfo,
+    State(state): State<AppState<Database>>,
+) -> ApiResult<impl IntoResponse> {
+    if let Some(ip_address) = &client.ip_address {
+        let ongoing = state.db.count_anonymous_sessions(ip_address).await?;
+        if ongoing >= MAX_LOGIN_CEREMONIES_PER_IP {
+            return Err(ApiError::BadRequest(
+                "Too many passkey logins started, try again in a few minutes".to_string(),
+            ));
+        }
+    }
+    let rp = RelyingParty::from_client_url(&state.env.client_url)?;
+    let (challenge, headers) = start_ceremony(&state.session_store, None, &client).await?;
+
+    // No allowed credentials, so the browser offers any passkey it holds for us
+    let options = json!({
+        "challenge": challenge,
+        "rpId": rp.id,
+        "userVerification": "required",
+        "timeout": CEREMONY_TTL.as_millis() as u64,
+    });
+    Ok((headers, Json(options)))
+}
+
+#[derive(Deserialize)]
+pub struct AssertionRequest {
+    id: String,
+    client_data: String,
+    authenticator_data: String,
+    signature: String,
+    user_handle: Option<String>,
+}
+
+// Check a login was signed by the passkey with `public_key` in answer to `challenge`,
+// returning the passkey's new signature count
+fn verify_assertion(
+    public_key: &[u8],
+    sign_count: i64,
+    assertion: &AssertionRequest,
+    challenge: &str,
+    rp: &RelyingParty,
+) -> ApiResult<i64> {
+    let client_data = decode(&assertion.client_data)?;
+    verify_client_data(&client_data, "webauthn.get", challenge, rp)?;
+    let raw_auth_data = decode(&assertion.authenticator_data)?;
+    let auth_data = parse_authenticator_data(&raw_auth_data, rp)?;
+
+    // The authenticator signs its data followed by a hash of the client data
+    let mut message = raw_auth_data;
+    message.extend_from_slice(&Sha256::digest(&client_data));
+    let key = PublicKey::from_cose(public_key)?;
+    if !key.verify(&message, &decode(&assertion.signature)?) {
+        return Err(invalid_passkey());
+    }
+
+    // Authenticators that count signatures must always count up, otherwise the
+    // key may have been cloned
+    let new_count = i64::from(auth_data.sign_count);
+    if (new_count != 0 || sign_count != 0) && new_count <= sign_count {
+        return Err(invalid_passkey());
+    }
+    Ok(new_count)
+}
+
+pub async fn passkey_login(
+    client: ClientInfo,
+    State(state): State<AppState<Database>>,
+    cookies: Option<TypedHeader<headers::Cookie>>,
+    Json(body): Json<AssertionRequest>,
+) -> ApiResult<impl IntoResponse> {
+    let challenge = take_ceremony(&state.session_store, cookies, None).await?;
+    let rp = RelyingParty::from_client_url(&state.env.client_url)?;
+
+    let passkey = state
+        .db
+        .get_passkey(&body.id)
+        .await?
+        .ok_or_else(|| ApiError::BadRequest("This passkey isn't registered".to_string()))?;
+    if let Some(user_handle) = &body.user_handle {
+        if decode(user_handle)? != passkey.user_id.as_bytes() {
+            return Err(invalid_passkey());
+        }
+    }
+    let sign_count = verify_assertion(
+        &passkey.public_key,
+        passkey.sign_count,
+        &body,
+        &challenge,
+        &rp,
+    )?;
+    state.db.update_passkey_use(&passkey.id, sign_count).await?;
+
+    let user = state
+        .db
+        .get_user(&passkey.user_id)
+        .await?
+        .context("passkey without a user")?;
+    let mut headers = clear_ceremony_cookie(&state.session_store)?;
+    let (session_headers, redirect) = login_user(&state, &user, &client, None).await?;
+    append_headers(&mut headers, session_headers);
+    Ok((headers, redirect))
+}
+
+#[derive(Deserialize)]
+pub struct RenamePasskeyRequest {
+    id: String,
+    name: String,
+}
+
+pub async fn rename_passkey(
+    SessionUser(user): SessionUser,
+    State(state): State<AppState<Database>>,
+    Form(body): Form<RenamePasskeyRequest>,
+) -> ApiResult<Html<String>> {
+    let name = passkey_name(&body.name)?;
+    state.db.rename_passkey(&user.id, &body.id, &name).await?;
+    let passkeys = state.db.get_passkeys(&user.id).await?;
+    Ok(Html(passkeys_card(&passkeys, None).into_string()))
+}
+
+#[derive(Deserialize)]
+pub struct RevokePasskeyRequest {
+    id: String,
+}
+
+pub async fn revoke_passkey(
+    SessionUser(user): SessionUser,
+    State(state): State<AppState<Database>>,
+    Form(body): Form<RevokePasskeyRequest>,
+) -> ApiResult<Html<String>> {
+    state.db.delete_passkey(&user.id, &body.id).await?;
+    let passkeys = state.db.get_passkeys(&user.id).await?;
+    Ok(Html(
+        passkeys_card(&passkeys, Some("Passkey removed")).into_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use ciborium::Value;
+    use p256::ecdsa::{signature::Signer, Signature, SigningKey};
+    use rand::rngs::OsRng;
+    use serde_json::json;
+    use sha2::{Digest, Sha256};
+
+    use super::{
+        verify_assertion, AssertionRequest, PublicKey, RelyingParty, COSE_ALG_ES256,
+        COSE_ALG_RS256, COSE_CRV_P256, COSE_KTY_EC2, COSE_KTY_RSA, FLAG_USER_PRESENT,
+        FLAG_USER_VERIFIED,
+    };
+    use crate::error::ApiError;
+
+    static CHALLENGE: &str = "challenge";
+    // P-384, which isn't what ES256 signs with
+    const COSE_CRV_P384: i64 = 2;
+
+    fn rp() -> RelyingParty {
+        RelyingParty::from_client_url("https://routines.example.com").unwrap()
+    }
+
+    fn cose_key(entries: Vec<(i64, Value)>) -> Vec<u8> {
+        let entries = entries
+            .into_iter()
+            .map(|(label, value)| (Value::Integer(label.into()), value))
+            .collect();
+        let mut bytes = vec![];
+        ciborium::into_writer(&Value::Map(entries), &mut bytes).unwrap();
+        bytes
+    }
+
+    fn ec2_key(key: &SigningKey, kty: i64, alg: i64, crv: i64) -> Vec<u8> {
+        let point = key.verifying_key().to_encoded_point(false);
+        cose_key(vec![
+            (1, Value::Integer(kty.into())),
+            (3, Value::Integer(alg.into())),
+            (-1, Value::Integer(crv.into())),
+            (-2, Value::Bytes(point.x().unwrap().to_vec())),
+            (-3, Value::Bytes(point.y().unwrap().to_vec())),
+        ])
+    }
+
+    fn es256_key(key: &SigningKey) -> Vec<u8> {
+        ec2_key(key, COSE_KTY_EC2, COSE_ALG_ES256, COSE_CRV_P256)
+    }
+
+    // What the browser sends back after `key` signs in with `sign_count`
+    fn assertion(key: &SigningKey, sign_count: u32) -> AssertionRequest {
+        let rp = rp();
+        let client_data = serde_json::to_vec(&json!({
+            "type": "webauthn.get",
+            "challenge": CHALLENGE,
+            "origin": rp.origin,
+        }))
+        .unwrap();
+        let mut auth_data = Sha256::digest(rp.id.as_bytes()).to_vec();
+        auth_data.push(FLAG_USER_PRESENT | FLAG_USER_VERIFIED);
+        auth_data.extend_from_slice(&sign_count.to_be_bytes());
+
+        let mut message = auth_data.clone();
+        message.extend_from_slice(&Sha256::digest(&client_data));
+        let signature: Signature = key.sign(&message);
+        AssertionRequest {
+            id: "passkey".to_string(),
+            client_data: URL_SAFE_NO_PAD.encode(client_data),
+            authenticator_data: URL_SAFE_NO_PAD.encode(auth_data),
+            signature: URL_SAFE_NO_PAD.encode(signature.to_der().as_bytes()),
+            user_handle: None,
+        }
+    }
+
+    fn verify(public_key: &[u8], sign_count: i64, assertion: &AssertionRequest) -> Option<i64> {
+        match verify_assertion(public_key, sign_count, assertion, CHALLENGE, &rp()) {
+            Ok(sign_count) => Some(sign_count),
+            Err(ApiError::BadRequest(_)) => None,
+            Err(e) => panic!("unexpected error {e:?}"),
+        }
+    }
+
+    #[test]
+    fn accepts_signed_assertions() {
+        let key = SigningKey::random(&mut OsRng);
+        assert_eq!(verify(&es256_key(&key), 4, &assertion(&key, 5)), Some(5));
+        // Authenticators that don't count signatures always send 0
+        assert_eq!(verify(&es256_key(&key), 0, &assertion(&key, 0)), Some(0));
+    }
+
+    #[test]
+    fn rejects_bad_signatures() {
+        let key = SigningKey::random(&mut OsRng);
+        let other = SigningKey::random(&mut OsRng);
+        assert_eq!(verify(&es256_key(&key), 0, &assertion(&other, 1)), None);
+
+        let mut tampered = assertion(&key, 1);
+        tampered.client_data = URL_SAFE_NO_PAD.encode(
+            json!({ "type": "webauthn.get", "challenge": "other", "origin": rp().origin })
+                .to_string(),
+        );
+        assert_eq!(verify(&es256_key(&key), 0, &tampered), None);
+    }
+
+    #[test]
+    fn rejects_sign_counts_that_dont_go_up() {
+        let key = SigningKey::random(&mut OsRng);
+        assert_eq!(verify(&es256_key(&key), 5, &assertion(&key, 5)), None);
+        assert_eq!(verify(&es256_key(&key), 5, &assertion(&key, 3)), None);
+        assert_eq!(verify(&es256_key(&key), 5, &assertion(&key, 0)), None);
+    }
+
+    #[test]
+    fn rejects_keys_that_dont_match_their_algorithm() {
+        let key = SigningKey::random(&mut OsRng);
+        let keys = [
+            ec2_key(&key, COSE_KTY_EC2, COSE_ALG_ES256, COSE_CRV_P384),
+            ec2_key(&key, COSE_KTY_RSA, COSE_ALG_ES256, COSE_CRV_P256),
+            ec2_key(&key, COSE_KTY_EC2, COSE_ALG_RS256, COSE_CRV_P256),
+            // EdDSA
+            ec2_key(&key, COSE_KTY_EC2, -8, COSE_CRV_P256),
+        ];
+        for public_key in keys {
+            assert!(PublicKey::from_cose(&public_key).is_err());
+            assert_eq!(verify(&public_key, 0, &assertion(&key, 1)), None);
+        }
+    }
+
+    #[test]
+    fn rejects_short_rsa_keys() {
+        let public_key = cose_key(vec![
+            (1, Value::Integer(COSE_KTY_RSA.into())),
+            (3, Value::Integer(COSE_ALG_RS256.into())),
+            (-1, Value::Bytes(vec![0xff; 128])),
+            (-2, Value::Bytes(vec![0x01, 0x00, 0x01])),
+        ]);
+        assert!(PublicKey::from_cose(&public_key).is_err());
+    }
+}
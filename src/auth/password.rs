@@ -0,0 +1,240 @@
+use anyhow::Context;
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::{
+    extract::{Query, State},
+    http::HeaderMap,
+    response::{Html, IntoResponse},
+    Form,
+};
+use axum_extra::{headers, TypedHeader};
+use serde::Deserialize;
+use std::sync::OnceLock;
+use uuid::Uuid;
+
+use super::{login_user, rotate_session, sessions::ClientInfo, LoginParams, SessionUser};
+use crate::{
+    database::Database,
+    error::{ApiError, ApiResult},
+    models::{
+        accounts::AccountDataLayer,
+        users::{RegistrationMode, UserDataLayer},
+    },
+    state::AppState,
+    templates::{login::register_page, settings::password_card},
+};
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+const MAX_PASSWORD_LENGTH: usize = 128;
+
+fn require_password_login(state: &AppState<Database>) -> ApiResult<()> {
+    if state.env.password_login {
+        Ok(())
+    } else {
+        Err(ApiError::NotFound)
+    }
+}
+
+// Usernames are case-insensitive, so they're stored lowercased
+fn normalize_username(username: &str) -> ApiResult<String> {
+    let username = username.trim().to_lowercase();
+    let valid_chars = username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.');
+    if !(3..=32).contains(&username.len()) || !valid_chars {
+        return Err(ApiError::BadRequest(
+            "Usernames must be 3-32 letters, numbers, dots, dashes or underscores".to_string(),
+        ));
+    }
+    Ok(username)
+}
+
+fn check_new_password(password: &str, confirm_password: &str) -> ApiResult<()> {
+    if password != confirm_password {
+        return Err(ApiError::BadRequest("Passwords don't match".to_string()));
+    }
+    if !(MIN_PASSWORD_LENGTH..=MAX_PASSWORD_LENGTH).contains(&password.chars().count()) {
+        return Err(ApiError::BadRequest(format!(
+            "Passwords must be between {MIN_PASSWORD_LENGTH} and {MAX_PASSWORD_LENGTH} characters"
+        )));
+    }
+    Ok(())
+}
+
+// Argon2id with the crate's default (OWASP recommended) parameters. Hashing is
+// deliberately slow, so keep it off the async runtime
+async fn hash_password(password: String) -> ApiResult<String> {
+    let hash = tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+    })
+    .await
+    .context("password hashing task failed")?
+    .map_err(|e| anyhow::anyhow!("failed to hash password: {e}"))?;
+    Ok(hash)
+}
+
+// Checked against a throwaway hash when there is no such user, so response times
+// don't give away which usernames exist
+async fn verify_password(password: String, hash: Option<String>) -> ApiResult<bool> {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+    let valid = tokio::task::spawn_blocking(move || {
+        let hash = hash.unwrap_or_else(|| {
+            DUMMY_HASH
+                .get_or_init(|| {
+                    Argon2::default()
+                        .hash_password(b"dummy password", &SaltString::generate(&mut OsRng))
+                        .map(|hash| hash.to_string())
+                        .unwrap_or_default()
+                })
+                .clone()
+        });
+        PasswordHash::new(&hash).is_ok_and(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+    })
+    .await
+    .context("password verification task failed")?;
+    Ok(valid)
+}
+
+#[derive(Deserialize)]
+pub struct PasswordLoginRequest {
+    username: String,
+    password: String,
+    return_to: Option<String>,
+}
+
+pub async fn password_login(
+    client: ClientInfo,
+    State(state): State<AppState<Database>>,
+    Form(body): Form<PasswordLoginRequest>,
+) -> ApiResult<impl IntoResponse> {
+    require_password_login(&state)?;
+
+    let username = body.username.trim().to_lowercase();
+    let login = state.db.get_password_login(&username).await?;
+    let (user_id, hash) = login.map_or((None, None), |l| (Some(l.user_id), Some(l.password_hash)));
+    let valid = verify_password(body.password, hash).await?;
+    let user_id = user_id
+        .filter(|_| valid)
+        .ok_or_else(|| ApiError::BadRequest("Incorrect username or password".to_string()))?;
+
+    let user = state
+        .db
+        .get_user(&user_id)
+        .await?
+        .context("password login without a user")?;
+    login_user(&state, &user, &client, body.return_to.as_deref()).await
+}
+
+pub async fn register_form(
+    Query(params): Query<LoginParams>,
+    State(state): State<AppState<Database>>,
+) -> ApiResult<Html<String>> {
+    require_password_login(&state)?;
+    let invite = params.invite.and_then(|i| Uuid::parse_str(&i).ok());
+    let registration = state.registration().await?;
+    if registration == RegistrationMode::Closed {
+        return Err(ApiError::Forbidden(
+            "Not currently accepting new sign ups".to_string(),
+        ));
+    }
+    if invite.is_none() && !registration.allows_uninvited() {
+        return Err(ApiError::Forbidden(
+            "An invite link is required to sign up".to_string(),
+        ));
+    }
+    Ok(Html(register_page(invite.as_ref()).into_string()))
+}
+
+#[derive(Deserialize)]
+pub struct RegisterRequest {
+    invite: Option<Uuid>,
+    username: String,
+    name: String,
+    password: String,
+    confirm_password: String,
+}
+
+pub async fn register(
+    client: ClientInfo,
+    State(state): State<AppState<Database>>,
+    Form(body): Form<RegisterRequest>,
+) -> ApiResult<impl IntoResponse> {
+    require_password_login(&state)?;
+
+    let username = normalize_username(&body.username)?;
+    check_new_password(&body.password, &body.confirm_password)?;
+    let name = match body.name.trim() {
+        "" => username.clone(),
+        name => name.to_string(),
+    };
+
+    let hash = hash_password(body.password).await?;
+    let user = state
+        .db
+        .register_password_user(
+            &username,
+            &name,
+            &hash,
+            body.invite.as_ref(),
+            state.env.registration,
+        )
+        .await?;
+
+    login_user(&state, &user, &client, None).await
+}
+
+#[derive(Deserialize)]
+pub struct ChangePasswordRequest {
+    // Only used when adding a password to an account that doesn't have one yet
+    username: Option<String>,
+    current_password: Option<String>,
+    new_password: String,
+    confirm_password: String,
+}
+
+pub async fn change_password(
+    SessionUser(user): SessionUser,
+    State(state): State<AppState<Database>>,
+    cookies: Option<TypedHeader<headers::Cookie>>,
+    Form(body): Form<ChangePasswordRequest>,
+) -> ApiResult<(HeaderMap, Html<String>)> {
+    require_password_login(&state)?;
+    check_new_password(&body.new_password, &body.confirm_password)?;
+
+    let username = match state.db.get_user_password_login(&user.id).await? {
+        Some(login) => {
+            let current = body.current_password.unwrap_or_default();
+            if !verify_password(current, Some(login.password_hash)).await? {
+                return Err(ApiError::BadRequest(
+                    "Current password is incorrect".to_string(),
+                ));
+            }
+            let hash = hash_password(body.new_password).await?;
+            state.db.set_password(&user.id, &hash).await?;
+            login.id
+        }
+        None => {
+            let username = normalize_username(&body.username.unwrap_or_default())?;
+            let hash = hash_password(body.new_password).await?;
+            state
+                .db
+                .add_password_login(&user.id, &username, &hash)
+                .await?;
+            username
+        }
+    };
+
+    let headers = rotate_session(&state.session_store, cookies.as_deref()).await?;
+    let markup = password_card(Some(&username), Some("Password updated"));
+    Ok((headers, Html(markup.into_string())))
+}
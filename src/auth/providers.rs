@@ -0,0 +1,522 @@
+use anyhow::{bail, Context, Result};
+use oauth2::{basic::BasicClient, AuthUrl, TokenUrl};
+use openidconnect::{
+    core::{
+        CoreAuthenticationFlow, CoreClient, CoreProviderMetadata, CoreTokenResponse,
+        CoreUserInfoClaims,
+    },
+    reqwest::async_http_client,
+    url::Url,
+    AuthorizationCode, ClientId, ClientSecret, CsrfToken, IssuerUrl, Nonce, OAuth2TokenResponse,
+    PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, Scope, TokenResponse,
+};
+use reqwest::Client;
+use serde::Deserialize;
+use std::sync::Arc;
+
+use super::UserResponse;
+use crate::{
+    error::{ApiError, ApiResult},
+    models::accounts::{EMAIL_PROVIDER, PASSWORD_PROVIDER},
+    state::Env,
+};
+
+static GITHUB_AUTH_URL: &str = "https://github.com/login/oauth/authorize";
+static GITHUB_TOKEN_URL: &str = "https://github.com/login/oauth/access_token";
+static GITHUB_USER_URL: &str = "https://api.github.com/user";
+static GITHUB_EMAILS_URL: &str = "https://api.github.com/user/emails";
+static GOOGLE_ISSUER_URL: &str = "https://accounts.google.com";
+
+// Name of the provider built from `CLIENT_ID` and `CLIENT_SECRET` when there's no
+// providers file, matching the accounts created before providers were configurable
+pub static LEGACY_PROVIDER: &str = "google";
+
+// One entry of the providers file, e.g.
+// { "name": "google", "label": "Google", "kind": "oidc",
+//   "issuer_url": "https://accounts.google.com", "client_id": "..", "client_secret": ".." }
+#[derive(Debug, Deserialize)]
+pub struct ProviderConfig {
+    pub name: String,
+    pub label: String,
+    pub client_id: String,
+    // Leave unset to run as a public client, relying on PKCE alone
+    pub client_secret: Option<String>,
+    // Where the provider sends users back to, `{CLIENT_URL}/auth/{name}/authorized` if unset
+    #[serde(default)]
+    pub redirect_url: Option<String>,
+    #[serde(flatten)]
+    pub kind: ProviderKind,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum ProviderKind {
+    // Any OpenID Connect issuer, configured through discovery
+    Oidc { issuer_url: String },
+    // GitHub only speaks plain OAuth 2, so identities come from its REST API
+    Github,
+}
+
+// Built once at startup and shared behind an `Arc`, so the size difference is moot
+#[allow(clippy::large_enum_variant)]
+enum ProviderClient {
+    Oidc(CoreClient),
+    Github(BasicClient),
+}
+
+pub struct AuthProvider {
+    // Used in routes and stored against each account, so must stay stable
+    pub name: String,
+    pub label: String,
+    client: ProviderClient,
+}
+
+// Where to send the user, and what to remember until they come back
+pub struct AuthorizeRequest {
+    pub url: Url,
+    pub csrf_token: CsrfToken,
+    pub pkce_verifier: PkceCodeVerifier,
+    pub nonce: Option<Nonce>,
+}
+
+impl AuthProvider {
+    async fn new(config: ProviderConfig, client_url: &str) -> Result<Self> {
+        if config.name.is_empty()
+            || !config
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            bail!("invalid provider name {:?}", config.name);
+        }
+        if config.name == PASSWORD_PROVIDER || config.name == EMAIL_PROVIDER {
+            bail!(
+                "provider name {} is reserved for built-in logins",
+                config.name
+            );
+        }
+
+        let client_id = ClientId::new(config.client_id);
+        let client_secret = config.client_secret.map(ClientSecret::new);
+        let redirect_url = RedirectUrl::new(config.redirect_url.unwrap_or_else(|| {
+            format!(
+                "{}/auth/{}/authorized",
+                client_url.trim_end_matches('/'),
+                config.name
+            )
+        }))
+        .context("failed to create new redirection URL")?;
+
+        let client = match config.kind {
+            ProviderKind::Oidc { issuer_url } => {
+                // Discover the issuer's endpoints and signing keys from its
+                // `.well-known/openid-configuration`
+                let issuer_url =
+                    IssuerUrl::new(issuer_url).context("failed to parse issuer URL")?;
+                let metadata = CoreProviderMetadata::discover_async(issuer_url, async_http_client)
+                    .await
+                    .with_context(|| {
+                        format!("failed to discover metadata for provider {}", config.name)
+                    })?;
+                ProviderClient::Oidc(
+                    CoreClient::from_provider_metadata(metadata, client_id, client_secret)
+                        .set_redirect_uri(redirect_url),
+                )
+            }
+            ProviderKind::Github => ProviderClient::Github(
+                BasicClient::new(
+                    client_id,
+                    client_secret,
+                    AuthUrl::new(GITHUB_AUTH_URL.to_string())
+                        .context("failed to create new authorization server URL")?,
+                    Some(
+                        TokenUrl::new(GITHUB_TOKEN_URL.to_string())
+                            .context("failed to create new token endpoint URL")?,
+                    ),
+                )
+                .set_redirect_uri(redirect_url),
+            ),
+        };
+
+        Ok(Self {
+            name: config.name,
+            label: config.label,
+            client,
+        })
+    }
+
+    pub fn authorize_url(&self) -> AuthorizeRequest {
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        match &self.client {
+            ProviderClient::Oidc(client) => {
+                let (url, csrf_token, nonce) = client
+                    .authorize_url(
+                        CoreAuthenticationFlow::AuthorizationCode,
+                        CsrfToken::new_random,
+                        Nonce::new_random,
+                    )
+                    .add_scope(Scope::new("profile".to_string()))
+                    .add_scope(Scope::new("email".to_string()))
+                    .set_pkce_challenge(pkce_challenge)
+                    .url();
+                AuthorizeRequest {
+                    url,
+                    csrf_token,
+                    pkce_verifier,
+                    nonce: Some(nonce),
+                }
+            }
+            ProviderClient::Github(client) => {
+                let (url, csrf_token) = client
+                    .authorize_url(CsrfToken::new_random)
+                    .add_scope(Scope::new("read:user".to_string()))
+                    .add_scope(Scope::new("user:email".to_string()))
+                    .set_pkce_challenge(pkce_challenge)
+                    .url();
+                AuthorizeRequest {
+                    url,
+                    csrf_token,
+                    pkce_verifier,
+                    nonce: None,
+                }
+            }
+        }
+    }
+
+    // Swap the authorization code for the identity of the user who logged in
+    pub async fn exchange_code(
+        &self,
+        code: String,
+        pkce_verifier: PkceCodeVerifier,
+        nonce: Option<Nonce>,
+        http_client: &Client,
+    ) -> ApiResult<UserResponse> {
+        match &self.client {
+            ProviderClient::Oidc(client) => {
+                let nonce = nonce.ok_or_else(|| {
+                    ApiError::BadRequest(
+                        "Login was missing its nonce, please try again".to_string(),
+                    )
+                })?;
+                let token = client
+                    .exchange_code(AuthorizationCode::new(code))
+                    .set_pkce_verifier(pkce_verifier)
+                    .request_async(async_http_client)
+                    .await
+                    .context("failed in sending request request to authorization server")?;
+                verify_id_token(client, &token, &nonce).await
+            }
+            ProviderClient::Github(client) => {
+                let token = client
+                    .exchange_code(AuthorizationCode::new(code))
+                    .set_pkce_verifier(pkce_verifier)
+                    .request_async(async_http_client)
+                    .await
+                    .context("failed in sending request request to authorization server")?;
+                github_user(http_client, token.access_token().secret()).await
+            }
+        }
+    }
+}
+
+// Check the ID token was issued by our provider, for us, and for this login
+// attempt, falling back to the userinfo endpoint for any profile claims it omits
+async fn verify_id_token(
+    client: &CoreClient,
+    token: &CoreTokenResponse,
+    nonce: &Nonce,
+) -> ApiResult<UserResponse> {
+    let id_token = token
+        .id_token()
+        .context("provider did not return an ID token")?;
+    let claims = id_token
+        .claims(&client.id_token_verifier(), nonce)
+        .map_err(|e| ApiError::BadRequest(format!("Login could not be verified: {e}")))?;
+
+    let mut email = claims.email().map(|e| e.to_string());
+    let mut email_verified = claims.email_verified();
+    let mut name = claims
+        .name()
+        .and_then(|n| n.get(None))
+        .map(|n| n.to_string());
+    if email.is_none() || name.is_none() {
+        let info: CoreUserInfoClaims = client
+            .user_info(token.access_token().clone(), Some(claims.subject().clone()))
+            .context("provider does not expose a userinfo endpoint")?
+            .request_async(async_http_client)
+            .await
+            .context("failed to fetch userinfo")?;
+        if email.is_none() {
+            email = info.email().map(|e| e.to_string());
+            email_verified = info.email_verified();
+        }
+        name = name.or(info.name().and_then(|n| n.get(None)).map(|n| n.to_string()));
+    }
+
+    let sub = claims.subject().to_string();
+    Ok(UserResponse {
+        name: name
+            .or(claims.preferred_username().map(|u| u.to_string()))
+            .or(email.clone())
+            .unwrap_or(sub.clone()),
+        email_verified: email.is_some() && email_verified.unwrap_or(false),
+        email,
+        sub,
+    })
+}
+
+async fn github_user(http_client: &Client, access_token: &str) -> ApiResult<UserResponse> {
+    #[derive(Deserialize)]
+    struct GithubUser {
+        id: u64,
+        login: String,
+        name: Option<String>,
+    }
+
+    let user: GithubUser = github_get(http_client, access_token, GITHUB_USER_URL)
+        .await
+        .context("failed to fetch GitHub user")?;
+    let emails: Vec<GithubEmail> = github_get(http_client, access_token, GITHUB_EMAILS_URL)
+        .await
+        .context("failed to fetch GitHub emails")?;
+    let email = primary_email(emails);
+
+    Ok(UserResponse {
+        sub: user.id.to_string(),
+        name: user.name.unwrap_or(user.login),
+        email_verified: email.as_ref().is_some_and(|e| e.verified),
+        email: email.map(|e| e.email),
+    })
+}
+
+async fn github_get<T: serde::de::DeserializeOwned>(
+    http_client: &Client,
+    access_token: &str,
+    url: &str,
+) -> Result<T> {
+    Ok(http_client
+        .get(url)
+        .bearer_auth(access_token)
+        .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+        .header(reqwest::header::USER_AGENT, "routines")
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?)
+}
+
+#[derive(Deserialize)]
+struct GithubEmail {
+    email: String,
+    primary: bool,
+    verified: bool,
+}
+
+// The profile email is whichever one the user chose to make public, and says nothing
+// about whether it's verified, so use their primary address from the emails API instead
+fn primary_email(emails: Vec<GithubEmail>) -> Option<GithubEmail> {
+    emails.into_iter().find(|e| e.primary)
+}
+
+#[derive(Clone)]
+pub struct Providers(Arc<Vec<AuthProvider>>);
+
+impl Providers {
+    // Use the providers file if there is one, otherwise fall back to a single Google
+    // provider configured the way it was before providers could be listed
+    pub async fn from_env(env: &Env) -> Result<Self> {
+        if let Some(path) = &env.providers_path {
+            return Self::load(path, &env.client_url).await;
+        }
+        let (Some(client_id), Some(client_secret)) = (&env.client_id, &env.client_secret) else {
+            bail!("set PROVIDERS_PATH, or CLIENT_ID and CLIENT_SECRET for Google logins");
+        };
+        let config = ProviderConfig {
+            name: LEGACY_PROVIDER.to_string(),
+            label: "Google".to_string(),
+            client_id: client_id.clone(),
+            client_secret: Some(client_secret.clone()),
+            redirect_url: env.redirect_url.clone(),
+            kind: ProviderKind::Oidc {
+                issuer_url: GOOGLE_ISSUER_URL.to_string(),
+            },
+        };
+        let provider = AuthProvider::new(config, &env.client_url).await?;
+        Ok(Self(Arc::new(vec![provider])))
+    }
+
+    // Read the providers file, e.g. `[{ "name": "google", ... }, { "name": "github", ... }]`
+    pub async fn load(path: &str, client_url: &str) -> Result<Self> {
+        let file = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("failed to read providers file {path}"))?;
+        let configs: Vec<ProviderConfig> =
+            serde_json::from_str(&file).context("failed to parse providers file")?;
+        if configs.is_empty() {
+            bail!("at least one login provider must be configured");
+        }
+
+        let mut providers: Vec<AuthProvider> = Vec::with_capacity(configs.len());
+        for config in configs {
+            if providers.iter().any(|p| p.name == config.name) {
+                bail!("provider {} is configured more than once", config.name);
+            }
+            providers.push(AuthProvider::new(config, client_url).await?);
+        }
+
+        Ok(Self(Arc::new(providers)))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&AuthProvider> {
+        self.0.iter().find(|p| p.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &AuthProvider> {
+        self.0.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use openidconnect::{
+        core::{
+            CoreClient, CoreHmacKey, CoreIdToken, CoreIdTokenClaims, CoreJwsSigningAlgorithm,
+            CoreProviderMetadata, CoreTokenResponse,
+        },
+        ClientId, ClientSecret, Nonce, PkceCodeVerifier,
+    };
+    use reqwest::Client;
+    use serde_json::{json, Value};
+    use time::OffsetDateTime;
+
+    use super::{primary_email, verify_id_token, AuthProvider, GithubEmail, ProviderClient};
+    use crate::error::ApiError;
+
+    static ISSUER: &str = "https://issuer.example.com";
+    static CLIENT_ID: &str = "routines";
+    static CLIENT_SECRET: &str = "client-secret";
+    static NONCE: &str = "expected-nonce";
+
+    // What the issuer's `.well-known/openid-configuration` would say. Tokens are signed
+    // with the client secret, so there are no keys to fetch
+    fn client() -> CoreClient {
+        let metadata: CoreProviderMetadata = serde_json::from_value(json!({
+            "issuer": ISSUER,
+            "authorization_endpoint": format!("{ISSUER}/authorize"),
+            "token_endpoint": format!("{ISSUER}/token"),
+            "jwks_uri": format!("{ISSUER}/jwks"),
+            "response_types_supported": ["code"],
+            "subject_types_supported": ["public"],
+            "id_token_signing_alg_values_supported": ["HS256"],
+        }))
+        .unwrap();
+        CoreClient::from_provider_metadata(
+            metadata,
+            ClientId::new(CLIENT_ID.to_string()),
+            Some(ClientSecret::new(CLIENT_SECRET.to_string())),
+        )
+    }
+
+    // Claims the client should accept, for the test to break one at a time
+    fn claims() -> Value {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        json!({
+            "iss": ISSUER,
+            "aud": [CLIENT_ID],
+            "exp": now + 600,
+            "iat": now,
+            "sub": "user-1",
+            "nonce": NONCE,
+            "name": "Ada",
+            "email": "ada@example.com",
+            "email_verified": true,
+        })
+    }
+
+    fn token_response(claims: Value) -> CoreTokenResponse {
+        let claims: CoreIdTokenClaims = serde_json::from_value(claims).unwrap();
+        let id_token = CoreIdToken::new(
+            claims,
+            &CoreHmacKey::new(CLIENT_SECRET),
+            CoreJwsSigningAlgorithm::HmacSha256,
+            None,
+            None,
+        )
+        .unwrap();
+        serde_json::from_value(json!({
+            "access_token": "access-token",
+            "token_type": "bearer",
+            "id_token": id_token,
+        }))
+        .unwrap()
+    }
+
+    async fn verify(claims: Value) -> Result<String, ApiError> {
+        let token = token_response(claims);
+        let user = verify_id_token(&client(), &token, &Nonce::new(NONCE.to_string())).await?;
+        Ok(user.sub)
+    }
+
+    #[tokio::test]
+    async fn accepts_valid_id_tokens() {
+        assert_eq!(verify(claims()).await.unwrap(), "user-1");
+    }
+
+    #[tokio::test]
+    async fn rejects_tokens_from_other_issuers() {
+        let mut claims = claims();
+        claims["iss"] = json!("https://attacker.example.com");
+        assert!(matches!(verify(claims).await, Err(ApiError::BadRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn rejects_tokens_for_other_clients() {
+        let mut claims = claims();
+        claims["aud"] = json!(["someone-else"]);
+        assert!(matches!(verify(claims).await, Err(ApiError::BadRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn rejects_tokens_without_the_nonce() {
+        let mut missing = claims();
+        missing.as_object_mut().unwrap().remove("nonce");
+        assert!(matches!(
+            verify(missing).await,
+            Err(ApiError::BadRequest(_))
+        ));
+
+        let mut other_login = claims();
+        other_login["nonce"] = json!("another-login");
+        assert!(matches!(
+            verify(other_login).await,
+            Err(ApiError::BadRequest(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_callbacks_that_lost_their_nonce() {
+        let provider = AuthProvider {
+            name: "issuer".to_string(),
+            label: "Issuer".to_string(),
+            client: ProviderClient::Oidc(client()),
+        };
+        let verifier = PkceCodeVerifier::new("verifier".to_string());
+        let result = provider
+            .exchange_code("code".to_string(), verifier, None, &Client::new())
+            .await;
+        assert!(matches!(result, Err(ApiError::BadRequest(_))));
+    }
+
+    #[test]
+    fn github_email_comes_from_the_primary_address() {
+        let emails: Vec<GithubEmail> = serde_json::from_value(json!([
+            { "email": "public@example.com", "primary": false, "verified": true },
+            { "email": "ada@example.com", "primary": true, "verified": false },
+        ]))
+        .unwrap();
+        let email = primary_email(emails).unwrap();
+        assert_eq!(email.email, "ada@example.com");
+        assert!(!email.verified);
+    }
+}
@@ -0,0 +1,87 @@
+use axum::response::{IntoResponse, Redirect, Response};
+use http::{request::Parts, HeaderName, Method, StatusCode};
+use openidconnect::url::{form_urlencoded, Url};
+
+// Sent by HTMX on every request it makes, and the page it was made from
+static HX_REQUEST: HeaderName = HeaderName::from_static("hx-request");
+static HX_CURRENT_URL: HeaderName = HeaderName::from_static("hx-current-url");
+// Tells HTMX to load a whole new page, rather than swapping the response in
+pub static HX_REDIRECT: HeaderName = HeaderName::from_static("hx-redirect");
+const MAX_RETURN_TO_LENGTH: usize = 512;
+
+// Only paths on this site can be returned to, so a crafted login link can't send
+// people somewhere else once they've logged in
+pub fn safe_return_to(return_to: &str) -> Option<&str> {
+    let path = return_to.split(['?', '#']).next().unwrap_or_default();
+    let safe = return_to.len() <= MAX_RETURN_TO_LENGTH
+        && return_to.starts_with('/')
+        // `//host` and `/\host` are both read as another origin by browsers
+        && !return_to.starts_with("//")
+        && !return_to.contains('\\')
+        && !return_to.chars().any(char::is_control)
+        // Coming back to these would just log the user straight out, or in again
+        && !["/logout", "/login", "/auth/"]
+            .iter()
+            .any(|prefix| path.starts_with(prefix));
+    safe.then_some(return_to)
+}
+
+// The login page, remembering where to go afterwards
+pub fn login_url(return_to: Option<&str>) -> String {
+    match return_to.and_then(safe_return_to) {
+        Some(return_to) => format!("/?{}", return_to_query(return_to)),
+        None => "/".to_string(),
+    }
+}
+
+pub fn return_to_query(return_to: &str) -> String {
+    form_urlencoded::Serializer::new(String::new())
+        .append_pair("return_to", return_to)
+        .finish()
+}
+
+// Where to send a request that needs a logged in user
+pub struct AuthRedirect {
+    htmx: bool,
+    return_to: Option<String>,
+}
+
+impl AuthRedirect {
+    pub fn for_request(parts: &Parts) -> Self {
+        let htmx = parts.headers.contains_key(&HX_REQUEST);
+        let return_to = if htmx {
+            // HTMX requests are for part of a page, so return to the page itself
+            parts
+                .headers
+                .get(&HX_CURRENT_URL)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| Url::parse(value).ok())
+                .map(|url| match url.query() {
+                    Some(query) => format!("{}?{query}", url.path()),
+                    None => url.path().to_string(),
+                })
+        } else if parts.method == Method::GET {
+            parts.uri.path_and_query().map(|path| path.to_string())
+        } else {
+            // There's no way to resubmit a form after logging in
+            None
+        };
+        Self {
+            htmx,
+            return_to: return_to.filter(|path| safe_return_to(path).is_some() && path != "/"),
+        }
+    }
+}
+
+impl IntoResponse for AuthRedirect {
+    fn into_response(self) -> Response {
+        // The login page lists every configured provider
+        let location = login_url(self.return_to.as_deref());
+        if self.htmx {
+            // A plain redirect would be followed by HTMX and the login page swapped into
+            // whatever made the request
+            return (StatusCode::UNAUTHORIZED, [(HX_REDIRECT.clone(), location)]).into_response();
+        }
+        Redirect::temporary(&location).into_response()
+    }
+}
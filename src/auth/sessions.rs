@@ -0,0 +1,130 @@
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRef, FromRequestParts, State},
+    response::Html,
+    Form,
+};
+use axum_extra::{headers, TypedHeader};
+use http::{header, request::Parts};
+use serde::Deserialize;
+use std::{convert::Infallible, net::SocketAddr};
+
+use super::{DBSessionStore, SessionUser};
+use crate::{
+    database::Database,
+    error::{ApiError, ApiResult},
+    models::{sessions::SessionDataLayer, users::User},
+    state::AppState,
+    templates::settings::sessions_card,
+};
+
+// Set by the Fly proxy, which is the only way in when deployed
+static CLIENT_IP_HEADER: &str = "fly-client-ip";
+const MAX_USER_AGENT_LENGTH: usize = 256;
+
+// Where a login came from, shown next to each session so users can recognise them
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    // The session this browser already has, which is replaced when it logs in
+    pub session_id: Option<String>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientInfo
+where
+    DBSessionStore<Database>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let store = DBSessionStore::from_ref(state);
+        let session_id = TypedHeader::<headers::Cookie>::from_request_parts(parts, state)
+            .await
+            .ok()
+            .and_then(|TypedHeader(cookies)| store.session_id(&cookies));
+        let user_agent = parts
+            .headers
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.chars().take(MAX_USER_AGENT_LENGTH).collect());
+        let ip_address = parts
+            .headers
+            .get(CLIENT_IP_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+            .or_else(|| {
+                parts
+                    .extensions
+                    .get::<ConnectInfo<SocketAddr>>()
+                    .map(|ConnectInfo(address)| address.ip().to_string())
+            });
+        Ok(Self {
+            user_agent,
+            ip_address,
+            session_id,
+        })
+    }
+}
+
+async fn render_sessions_card(
+    state: &AppState<Database>,
+    user: &User,
+    current: Option<&str>,
+    notice: Option<&str>,
+) -> ApiResult<Html<String>> {
+    let sessions = state.db.get_user_sessions(&user.id).await?;
+    Ok(Html(
+        sessions_card(&sessions, current, notice).into_string(),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct RevokeSessionRequest {
+    id: String,
+}
+
+pub async fn revoke_session(
+    SessionUser(user): SessionUser,
+    State(state): State<AppState<Database>>,
+    TypedHeader(cookies): TypedHeader<headers::Cookie>,
+    Form(body): Form<RevokeSessionRequest>,
+) -> ApiResult<Html<String>> {
+    let current = state.session_store.session_id(&cookies);
+    // Logging out is how to end the session in use
+    if current.as_deref() == Some(body.id.as_str()) {
+        return Err(ApiError::BadRequest(
+            "Log out to end the session you're using".to_string(),
+        ));
+    }
+    if state.db.delete_user_session(&user.id, &body.id).await? == 0 {
+        return Err(ApiError::NotFound);
+    }
+    render_sessions_card(
+        &state,
+        &user,
+        current.as_deref(),
+        Some("Session logged out"),
+    )
+    .await
+}
+
+pub async fn revoke_other_sessions(
+    SessionUser(user): SessionUser,
+    State(state): State<AppState<Database>>,
+    TypedHeader(cookies): TypedHeader<headers::Cookie>,
+) -> ApiResult<Html<String>> {
+    let current = state.session_store.session_id(&cookies).ok_or_else(|| {
+        ApiError::BadRequest("Only a logged in browser can end its other sessions".to_string())
+    })?;
+    let revoked = state
+        .db
+        .delete_other_user_sessions(&user.id, &current)
+        .await?;
+    let notice = match revoked {
+        1 => "Logged out of 1 other session".to_string(),
+        n => format!("Logged out of {n} other sessions"),
+    };
+    render_sessions_card(&state, &user, Some(&current), Some(&notice)).await
+}
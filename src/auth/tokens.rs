@@ -0,0 +1,173 @@
+use anyhow::Context;
+use axum::{extract::State, http::Method, response::Html, Form};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::RngCore;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+
+use super::{disabled_user, SessionUser};
+use crate::{
+    database::Database,
+    error::{ApiError, ApiResult},
+    models::{
+        api_tokens::{ApiTokenDataLayer, TokenScope},
+        users::{User, UserDataLayer},
+    },
+    state::AppState,
+    templates::settings::api_tokens_card,
+};
+
+// Makes leaked tokens easy to spot, e.g. by secret scanners
+const TOKEN_PREFIX: &str = "rtn_";
+const MAX_NAME_LENGTH: usize = 64;
+
+// Tokens carry 256 random bits, so unlike passwords a fast hash is enough
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+// Find the user behind an `Authorization: Bearer` token, checking it's allowed to
+// make this request
+pub async fn authenticate_token(db: &Database, token: &str, method: &Method) -> ApiResult<User> {
+    let token = db
+        .find_api_token(&hash_token(token))
+        .await?
+        .ok_or_else(|| ApiError::Unauthorized("Invalid API token".to_string()))?;
+    if token.is_expired() {
+        return Err(ApiError::Unauthorized(
+            "This API token has expired".to_string(),
+        ));
+    }
+    if token.scope == TokenScope::Read && !method.is_safe() {
+        return Err(ApiError::Forbidden(
+            "This API token is read-only".to_string(),
+        ));
+    }
+
+    db.touch_api_token(&token.id).await?;
+    let user = db
+        .get_user(&token.user_id)
+        .await?
+        .context("API token without a user")?;
+    if user.is_disabled() {
+        return Err(disabled_user());
+    }
+    if user.pending {
+        return Err(ApiError::Forbidden(
+            "This account is waiting to be approved".to_string(),
+        ));
+    }
+    Ok(user)
+}
+
+#[derive(Deserialize)]
+pub struct CreateTokenRequest {
+    name: String,
+    scope: TokenScope,
+    // Days until it expires, or "never"
+    expires: String,
+}
+
+pub async fn create_api_token(
+    SessionUser(user): SessionUser,
+    State(state): State<AppState<Database>>,
+    Form(body): Form<CreateTokenRequest>,
+) -> ApiResult<Html<String>> {
+    let name = body.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(ApiError::BadRequest(format!(
+            "Token names must be 1-{MAX_NAME_LENGTH} characters"
+        )));
+    }
+    let expires_at = match body.expires.as_str() {
+        "never" => None,
+        days => {
+            let days: i64 = days
+                .parse()
+                .ok()
+                .filter(|days| (1..=365).contains(days))
+                .ok_or_else(|| ApiError::BadRequest("Invalid token expiry".to_string()))?;
+            Some(OffsetDateTime::now_utc() + Duration::days(days))
+        }
+    };
+
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token = format!("{TOKEN_PREFIX}{}", URL_SAFE_NO_PAD.encode(bytes));
+    state
+        .db
+        .create_api_token(&user.id, name, &hash_token(&token), body.scope, expires_at)
+        .await?;
+
+    let tokens = state.db.get_api_tokens(&user.id).await?;
+    Ok(Html(api_tokens_card(&tokens, Some(&token)).into_string()))
+}
+
+#[derive(Deserialize)]
+pub struct RevokeTokenRequest {
+    id: Uuid,
+}
+
+pub async fn revoke_api_token(
+    SessionUser(user): SessionUser,
+    State(state): State<AppState<Database>>,
+    Form(body): Form<RevokeTokenRequest>,
+) -> ApiResult<Html<String>> {
+    state.db.delete_api_token(&user.id, &body.id).await?;
+    let tokens = state.db.get_api_tokens(&user.id).await?;
+    Ok(Html(api_tokens_card(&tokens, None).into_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+    use time::OffsetDateTime;
+    use uuid::Uuid;
+
+    use super::{authenticate_token, hash_token};
+    use crate::{
+        database::{test_database, Database},
+        error::ApiError,
+        models::api_tokens::{ApiTokenDataLayer, TokenScope},
+    };
+
+    async fn user_with_token(db: &Database, pending: bool, scope: TokenScope) -> Uuid {
+        let id = Uuid::new_v4();
+        sqlx::query(r#"INSERT INTO user (id, name, created_at, pending) VALUES ($1, $2, $3, $4)"#)
+            .bind(id)
+            .bind("someone")
+            .bind(OffsetDateTime::now_utc())
+            .bind(pending)
+            .execute(&db.db)
+            .await
+            .unwrap();
+        db.create_api_token(&id, "script", &hash_token("secret"), scope, None)
+            .await
+            .unwrap();
+        id
+    }
+
+    #[tokio::test]
+    async fn tokens_authenticate_their_user() {
+        let db = test_database().await;
+        let id = user_with_token(&db, false, TokenScope::Read).await;
+
+        let user = authenticate_token(&db, "secret", &Method::GET).await;
+        assert_eq!(user.unwrap().id, id);
+        let result = authenticate_token(&db, "secret", &Method::POST).await;
+        assert!(matches!(result, Err(ApiError::Forbidden(_))));
+        let result = authenticate_token(&db, "guess", &Method::GET).await;
+        assert!(matches!(result, Err(ApiError::Unauthorized(_))));
+    }
+
+    #[tokio::test]
+    async fn pending_users_tokens_are_rejected() {
+        let db = test_database().await;
+        user_with_token(&db, true, TokenScope::ReadWrite).await;
+
+        let result = authenticate_token(&db, "secret", &Method::GET).await;
+        assert!(matches!(result, Err(ApiError::Forbidden(_))));
+    }
+}
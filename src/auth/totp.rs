@@ -0,0 +1,370 @@
+use anyhow::Context;
+use async_session::{Session, SessionStore};
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts, State},
+    response::{Html, IntoResponse, Redirect, Response},
+    Form,
+};
+use axum_extra::{headers, TypedHeader};
+use http::request::Parts;
+use qrcode::QrCode;
+use rand::{Rng, RngCore};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    future::Future,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use time::OffsetDateTime;
+use totp_rs::{Algorithm, Secret, TOTP};
+use uuid::Uuid;
+
+use super::{
+    redirect::safe_return_to, request_session, rotate_session, session_user, sessions::ClientInfo,
+    start_session, AuthRedirect, DBSessionStore, SessionUser, RETURN_TO_KEY, TOTP_PENDING_KEY,
+};
+use crate::{
+    database::Database,
+    error::{ApiError, ApiResult},
+    models::{
+        totp::{Totp, TotpDataLayer},
+        users::User,
+    },
+    state::AppState,
+    templates::{
+        login::totp_page,
+        settings::{totp_card, TotpCard},
+    },
+};
+
+const TOTP_STEP: u64 = 30;
+const TOTP_DIGITS: usize = 6;
+const TOTP_ISSUER: &str = "Routines";
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz234567";
+// Wrong codes allowed before a pending login is thrown away
+const MAX_TOTP_ATTEMPTS: u32 = 5;
+// ...and in a row across all of a user's sessions before they're locked out for a while
+const MAX_TOTP_FAILURES: i64 = 5;
+const TOTP_LOCKOUT: Duration = Duration::from_secs(15 * 60);
+static TOTP_ATTEMPTS_KEY: &str = "totp_attempts";
+
+fn build_totp(secret: &str, account_name: &str) -> ApiResult<TOTP> {
+    let secret = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .context("invalid TOTP secret")?;
+    // Authenticator apps label the account as `issuer:account name`
+    let totp = TOTP::new(
+        Algorithm::SHA1,
+        TOTP_DIGITS,
+        0,
+        TOTP_STEP,
+        secret,
+        Some(TOTP_ISSUER.to_string()),
+        account_name.replace(':', " "),
+    )
+    .context("failed to create TOTP")?;
+    Ok(totp)
+}
+
+// Codes are typed by hand, so ignore spacing, dashes and case
+fn normalize_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+// Find the time step a code belongs to, allowing one step either side for clock drift
+fn matching_step(totp: &TOTP, code: &str) -> ApiResult<Option<u64>> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .context("system clock is set before 1970")?
+        .as_secs();
+    let current = now / TOTP_STEP;
+    Ok([current - 1, current, current + 1]
+        .into_iter()
+        .find(|step| totp.check(code, step * TOTP_STEP)))
+}
+
+// Accept a code from the user's authenticator, as long as it hasn't been used before
+async fn check_totp_code(
+    db: &Database,
+    user_id: &Uuid,
+    totp: &TOTP,
+    code: &str,
+) -> ApiResult<bool> {
+    match matching_step(totp, &normalize_code(code))? {
+        Some(step) => db.use_totp_step(user_id, step as i64).await,
+        None => Ok(false),
+    }
+}
+
+// Recovery codes carry 50 random bits, so unlike passwords a fast hash is enough
+fn hash_recovery_code(code: &str) -> String {
+    hex::encode(Sha256::digest(normalize_code(code).as_bytes()))
+}
+
+// Returns the codes to show the user once, and the hashes to store
+fn generate_recovery_codes() -> (Vec<String>, Vec<String>) {
+    let mut rng = rand::thread_rng();
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code: String = (0..10)
+                .map(|_| {
+                    RECOVERY_CODE_ALPHABET[rng.gen_range(0..RECOVERY_CODE_ALPHABET.len())] as char
+                })
+                .collect();
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect();
+    let hashes = codes.iter().map(|code| hash_recovery_code(code)).collect();
+    (codes, hashes)
+}
+
+// Run `check` against the user's lockout, counting it as a failure unless it passes
+async fn limit_attempts(
+    db: &Database,
+    user_id: &Uuid,
+    check: impl Future<Output = ApiResult<bool>>,
+) -> ApiResult<bool> {
+    let locked_until = OffsetDateTime::now_utc() + TOTP_LOCKOUT;
+    if !db
+        .start_totp_attempt(user_id, MAX_TOTP_FAILURES, locked_until)
+        .await?
+    {
+        return Err(ApiError::Forbidden(
+            "Too many incorrect codes, try again in a few minutes".to_string(),
+        ));
+    }
+    let valid = check.await?;
+    if valid {
+        db.clear_totp_failures(user_id).await?;
+    }
+    Ok(valid)
+}
+
+// Either a current authenticator code or one of the user's unused recovery codes
+async fn check_second_factor(
+    db: &Database,
+    user: &User,
+    totp: &Totp,
+    code: &str,
+) -> ApiResult<bool> {
+    let authenticator = build_totp(&totp.secret, &user.name)?;
+    limit_attempts(db, &user.id, async {
+        if check_totp_code(db, &user.id, &authenticator, code).await? {
+            return Ok(true);
+        }
+        db.use_recovery_code(&user.id, &hash_recovery_code(code))
+            .await
+    })
+    .await
+}
+
+// A session that has passed its primary login but still needs a TOTP code
+pub struct PendingLogin {
+    session: Session,
+    user: User,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for PendingLogin
+where
+    DBSessionStore<Database>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let store = DBSessionStore::from_ref(state);
+        let session = request_session(parts, &store).await?;
+        if !session.get::<bool>(TOTP_PENDING_KEY).unwrap_or(false) {
+            return Err(AuthRedirect::for_request(parts).into_response());
+        }
+        let user = session_user(&store, &session)
+            .await
+            .map_err(IntoResponse::into_response)?
+            .ok_or_else(|| AuthRedirect::for_request(parts).into_response())?;
+        Ok(Self { session, user })
+    }
+}
+
+pub async fn totp_form(_pending: PendingLogin) -> Html<String> {
+    Html(totp_page(None).into_string())
+}
+
+#[derive(Deserialize)]
+pub struct CodeRequest {
+    code: String,
+}
+
+pub async fn totp_login(
+    PendingLogin { mut session, user }: PendingLogin,
+    client: ClientInfo,
+    State(state): State<AppState<Database>>,
+    Form(body): Form<CodeRequest>,
+) -> ApiResult<Response> {
+    let store = &state.session_store;
+    let attempts = session.get::<u32>(TOTP_ATTEMPTS_KEY).unwrap_or(0);
+    if attempts >= MAX_TOTP_ATTEMPTS {
+        store
+            .destroy_session(session)
+            .await
+            .context("failed to destroy session")?;
+        return Err(ApiError::Forbidden(
+            "Too many incorrect codes, please log in again".to_string(),
+        ));
+    }
+
+    // If TOTP was turned off since this login started there's nothing left to check
+    let valid = match state.db.get_totp(&user.id).await? {
+        Some(totp) if totp.is_enabled() => {
+            check_second_factor(&state.db, &user, &totp, &body.code).await?
+        }
+        _ => true,
+    };
+    if !valid {
+        session
+            .insert(TOTP_ATTEMPTS_KEY, attempts + 1)
+            .context("failed in inserting serialized value into session")?;
+        store
+            .store_session(session)
+            .await
+            .context("failed to store session")?;
+        let markup = totp_page(Some("That code is incorrect"));
+        return Ok(Html(markup.into_string()).into_response());
+    }
+
+    // Swap the pending session for a fresh, fully logged in one
+    let return_to = session
+        .get::<String>(RETURN_TO_KEY)
+        .filter(|return_to| safe_return_to(return_to).is_some())
+        .unwrap_or_else(|| "/".to_string());
+    store
+        .destroy_session(session)
+        .await
+        .context("failed to destroy session")?;
+    let headers = start_session(store, &user, &client, false, None).await?;
+    Ok((headers, Redirect::to(&return_to)).into_response())
+}
+
+pub async fn start_totp_setup(
+    SessionUser(user): SessionUser,
+    State(state): State<AppState<Database>>,
+) -> ApiResult<Html<String>> {
+    let mut secret = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut secret);
+    let secret = Secret::Raw(secret.to_vec()).to_encoded().to_string();
+    state.db.start_totp_enrolment(&user.id, &secret).await?;
+
+    let authenticator = build_totp(&secret, &user.name)?;
+    let qr_code = QrCode::new(authenticator.get_url()).context("failed to create QR code")?;
+    let markup = totp_card(
+        TotpCard::Enrolling {
+            qr_code: &qr_code,
+            secret: &secret,
+        },
+        None,
+    );
+    Ok(Html(markup.into_string()))
+}
+
+pub async fn enable_totp(
+    SessionUser(user): SessionUser,
+    State(state): State<AppState<Database>>,
+    cookies: Option<TypedHeader<headers::Cookie>>,
+    Form(body): Form<CodeRequest>,
+) -> ApiResult<Response> {
+    let totp = state
+        .db
+        .get_totp(&user.id)
+        .await?
+        .filter(|totp| !totp.is_enabled())
+        .ok_or_else(|| {
+            ApiError::BadRequest("Start setting up two-factor authentication first".to_string())
+        })?;
+
+    // Only turn it on once the user has shown their authenticator works
+    let authenticator = build_totp(&totp.secret, &user.name)?;
+    if !check_totp_code(&state.db, &user.id, &authenticator, &body.code).await? {
+        let qr_code = QrCode::new(authenticator.get_url()).context("failed to create QR code")?;
+        let markup = totp_card(
+            TotpCard::Enrolling {
+                qr_code: &qr_code,
+                secret: &totp.secret,
+            },
+            Some("That code is incorrect"),
+        );
+        return Ok(Html(markup.into_string()).into_response());
+    }
+
+    let (codes, hashes) = generate_recovery_codes();
+    state.db.enable_totp(&user.id, &hashes).await?;
+    let headers = rotate_session(&state.session_store, cookies.as_deref()).await?;
+    let markup = totp_card(
+        TotpCard::NewRecoveryCodes(&codes),
+        Some("Two-factor authentication is on"),
+    );
+    Ok((headers, Html(markup.into_string())).into_response())
+}
+
+// Fetch the user's enabled TOTP, or fail if they haven't turned it on
+async fn enabled_totp(state: &AppState<Database>, user: &User) -> ApiResult<Totp> {
+    state
+        .db
+        .get_totp(&user.id)
+        .await?
+        .filter(|totp| totp.is_enabled())
+        .ok_or_else(|| ApiError::BadRequest("Two-factor authentication is off".to_string()))
+}
+
+async fn incorrect_code_card(state: &AppState<Database>, user: &User) -> ApiResult<Html<String>> {
+    let recovery_codes_left = state.db.count_recovery_codes(&user.id).await?;
+    let markup = totp_card(
+        TotpCard::On {
+            recovery_codes_left,
+        },
+        Some("That code is incorrect"),
+    );
+    Ok(Html(markup.into_string()))
+}
+
+pub async fn disable_totp(
+    SessionUser(user): SessionUser,
+    State(state): State<AppState<Database>>,
+    cookies: Option<TypedHeader<headers::Cookie>>,
+    Form(body): Form<CodeRequest>,
+) -> ApiResult<Response> {
+    let totp = enabled_totp(&state, &user).await?;
+    if !check_second_factor(&state.db, &user, &totp, &body.code).await? {
+        return Ok(incorrect_code_card(&state, &user).await?.into_response());
+    }
+
+    state.db.disable_totp(&user.id).await?;
+    let headers = rotate_session(&state.session_store, cookies.as_deref()).await?;
+    let markup = totp_card(TotpCard::Off, Some("Two-factor authentication is off"));
+    Ok((headers, Html(markup.into_string())).into_response())
+}
+
+pub async fn regenerate_recovery_codes(
+    SessionUser(user): SessionUser,
+    State(state): State<AppState<Database>>,
+    Form(body): Form<CodeRequest>,
+) -> ApiResult<Html<String>> {
+    let totp = enabled_totp(&state, &user).await?;
+    let authenticator = build_totp(&totp.secret, &user.name)?;
+    let check = check_totp_code(&state.db, &user.id, &authenticator, &body.code);
+    if !limit_attempts(&state.db, &user.id, check).await? {
+        return incorrect_code_card(&state, &user).await;
+    }
+
+    let (codes, hashes) = generate_recovery_codes();
+    state.db.replace_recovery_codes(&user.id, &hashes).await?;
+    let markup = totp_card(
+        TotpCard::NewRecoveryCodes(&codes),
+        Some("Your old recovery codes no longer work"),
+    );
+    Ok(Html(markup.into_string()))
+}
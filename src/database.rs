@@ -1,6 +1,15 @@
 use crate::models::{
-    entries::RoutineEntryDataLayer, invites::InviteDataLayer, routines::RoutineDataLayer,
-    sessions::SessionDataLayer, users::UserDataLayer,
+    accounts::AccountDataLayer,
+    admin::AdminDataLayer,
+    api_tokens::ApiTokenDataLayer,
+    entries::RoutineEntryDataLayer,
+    invites::InviteDataLayer,
+    magic_links::MagicLinkDataLayer,
+    passkeys::PasskeyDataLayer,
+    routines::RoutineDataLayer,
+    sessions::SessionDataLayer,
+    totp::TotpDataLayer,
+    users::{UserCache, UserDataLayer},
 };
 use anyhow::Result;
 use sqlx::{Pool, Sqlite, SqlitePool};
@@ -17,6 +26,12 @@ pub trait DataLayer<'a>:
     + SessionDataLayer
     + RoutineEntryDataLayer
     + InviteDataLayer
+    + AccountDataLayer
+    + ApiTokenDataLayer
+    + MagicLinkDataLayer
+    + PasskeyDataLayer
+    + TotpDataLayer
+    + AdminDataLayer
     + 'a
 {
 }
@@ -24,17 +39,22 @@ pub trait DataLayer<'a>:
 #[derive(Clone, Debug)]
 pub struct Database {
     pub db: Pool<Sqlite>,
+    pub users: UserCache,
 }
 
 impl Database {
     pub fn new(db: Pool<Sqlite>) -> Self {
-        Self { db }
+        Self {
+            db,
+            users: UserCache::default(),
+        }
     }
 }
 
 pub async fn setup_database(path: &str) -> Result<Pool<Sqlite>> {
     OpenOptions::new()
         .create(true)
+        .truncate(false)
         .write(true)
         .open(path)
         .await?;
@@ -46,3 +66,20 @@ pub async fn setup_database(path: &str) -> Result<Pool<Sqlite>> {
 }
 
 impl DataLayer<'_> for Database {}
+
+// A fresh, migrated database for tests to run against
+#[cfg(test)]
+pub async fn test_database() -> Database {
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    // Every connection to `:memory:` gets a database of its own, so stick to one
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
+    Database::new(pool)
+}
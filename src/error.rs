@@ -1,8 +1,18 @@
-use axum::response::{IntoResponse, Response};
+use axum::response::{Html, IntoResponse, Response};
 use http::StatusCode;
 
+use crate::templates::error::error_page;
+
 #[derive(Debug)]
-pub struct ApiError(pub anyhow::Error);
+pub enum ApiError {
+    // The request was refused, e.g. a login callback that failed validation
+    BadRequest(String),
+    // Credentials were given but aren't valid, e.g. an expired API token
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound,
+    Internal(anyhow::Error),
+}
 
 // impl From<sqlx::Error> for ApiError {
 //     fn from(value: sqlx::Error) -> Self {
@@ -13,9 +23,31 @@ pub struct ApiError(pub anyhow::Error);
 // Tell axum how to convert `ApiError` into a response.
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
-        tracing::error!("Application error: {:#}", self.0);
+        let (status, message) = match self {
+            Self::BadRequest(message) => {
+                tracing::warn!("Rejected request: {message}");
+                (StatusCode::BAD_REQUEST, message)
+            }
+            Self::Unauthorized(message) => {
+                tracing::warn!("Unauthorized request: {message}");
+                (StatusCode::UNAUTHORIZED, message)
+            }
+            Self::Forbidden(message) => {
+                tracing::warn!("Forbidden request: {message}");
+                (StatusCode::FORBIDDEN, message)
+            }
+            Self::NotFound => (StatusCode::NOT_FOUND, "This page doesn't exist".to_string()),
+            Self::Internal(err) => {
+                tracing::error!("Application error: {:#}", err);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Something went wrong".to_string(),
+                )
+            }
+        };
 
-        (StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong").into_response()
+        let title = status.canonical_reason().unwrap_or("Error");
+        (status, Html(error_page(title, &message).into_string())).into_response()
     }
 }
 
@@ -26,7 +58,7 @@ where
     E: Into<anyhow::Error>,
 {
     fn from(err: E) -> Self {
-        Self(err.into())
+        Self::Internal(err.into())
     }
 }
 
@@ -1,23 +1,50 @@
 #![allow(async_fn_in_trait)]
 
 use anyhow::Context;
-use auth::{google_auth, login_authorized, logout, protected};
+use auth::{
+    email::{email_login, send_magic_link, verify_magic_link},
+    legacy_login_authorized, login_authorized, logout,
+    passkey::{
+        login_options, passkey_login, register_passkey, registration_options, rename_passkey,
+        revoke_passkey,
+    },
+    password::{change_password, password_login, register, register_form},
+    protected, provider_login,
+    providers::Providers,
+    purge_expired_sessions, renew_session,
+    sessions::{revoke_other_sessions, revoke_session},
+    tokens::{create_api_token, revoke_api_token},
+    totp::{
+        disable_totp, enable_totp, regenerate_recovery_codes, start_totp_setup, totp_form,
+        totp_login,
+    },
+};
 use axum::{
+    middleware,
     routing::{get, post},
     Router,
 };
 use clap::Parser;
 use database::{setup_database, Database};
 use dotenvy::dotenv;
+use mailer::mailer_from_env;
 use r#static::static_router;
-use routes::{create_invite, create_routine, root, toggle_entry};
+use routes::{
+    account_settings, admin_dashboard, approve_user, archive_routine, archived_routines,
+    create_invite, create_invite_from_page, create_routine, delete_account, delete_routine,
+    delete_user, edit_routine_form, export_account, get_routine_card, pause_routine,
+    restore_routine, resume_routine, revoke_invite, revoke_sent_invite, root, sent_invites,
+    set_user_admin, set_user_disabled, toggle_entry, unlink_identity, update_profile,
+    update_routine,
+};
 use state::{AppState, Env};
-use std::env;
+use std::{env, net::SocketAddr};
 use tower_http::trace::TraceLayer;
 
 mod auth;
 mod database;
 mod error;
+mod mailer;
 mod models;
 mod routes;
 mod state;
@@ -35,25 +62,92 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let pool = setup_database(&env.database_path)
         .await
         .context("Failed to setup database")?;
-    let oauth = auth::oauth_client(&env).expect("Failed to build oauth client");
+    let providers = Providers::from_env(&env)
+        .await
+        .context("Failed to set up login providers")?;
+    let mailer = mailer_from_env(&env).context("Failed to set up mailer")?;
+
+    let state = AppState::new(Database::new(pool), env, providers, mailer);
+    tokio::spawn(purge_expired_sessions(state.db.clone()));
 
-    let state = AppState::new(Database::new(pool), env, oauth);
     let app = Router::new()
         .route("/", get(root))
         .route("/routine", post(create_routine))
+        .route(
+            "/routine/:id",
+            get(get_routine_card)
+                .post(update_routine)
+                .delete(delete_routine),
+        )
+        .route("/routine/:id/edit", get(edit_routine_form))
+        .route("/routine/:id/archive", post(archive_routine))
+        .route("/routine/:id/restore", post(restore_routine))
+        .route("/routine/:id/pause", post(pause_routine))
+        .route("/routine/:id/resume", post(resume_routine))
+        .route("/archived", get(archived_routines))
         .route("/entry", post(toggle_entry))
         .route("/invite", post(create_invite))
+        .route("/invites", get(sent_invites).post(create_invite_from_page))
+        .route("/invites/revoke", post(revoke_sent_invite))
+        .route("/settings", get(account_settings))
+        .route("/settings/profile", post(update_profile))
+        .route("/settings/identities/unlink", post(unlink_identity))
+        .route("/settings/export", get(export_account))
+        .route("/settings/delete", post(delete_account))
+        .route("/settings/password", post(change_password))
+        .route("/settings/tokens", post(create_api_token))
+        .route("/settings/tokens/revoke", post(revoke_api_token))
+        .route("/settings/sessions/revoke", post(revoke_session))
+        .route(
+            "/settings/sessions/revoke-others",
+            post(revoke_other_sessions),
+        )
+        .route("/settings/passkeys", post(register_passkey))
+        .route("/settings/passkeys/options", post(registration_options))
+        .route("/settings/passkeys/rename", post(rename_passkey))
+        .route("/settings/passkeys/revoke", post(revoke_passkey))
+        .route("/settings/totp/setup", post(start_totp_setup))
+        .route("/settings/totp/enable", post(enable_totp))
+        .route("/settings/totp/disable", post(disable_totp))
+        .route(
+            "/settings/totp/recovery-codes",
+            post(regenerate_recovery_codes),
+        )
+        .route("/admin", get(admin_dashboard))
+        .route("/admin/users/disable", post(set_user_disabled))
+        .route("/admin/users/admin", post(set_user_admin))
+        .route("/admin/users/approve", post(approve_user))
+        .route("/admin/users/delete", post(delete_user))
+        .route("/admin/invites/revoke", post(revoke_invite))
+        .route("/login", post(password_login))
+        .route("/login/passkey/options", post(login_options))
+        .route("/login/passkey", post(passkey_login))
+        .route("/login/totp", get(totp_form).post(totp_login))
+        .route("/login/email", post(send_magic_link))
+        .route(
+            "/login/email/verify",
+            get(verify_magic_link).post(email_login),
+        )
+        .route("/register", get(register_form).post(register))
         .route("/static/*path", get(static_router))
-        .route("/auth/google", get(google_auth))
-        .route("/auth/authorized", get(login_authorized))
+        .route("/auth/:provider", get(provider_login))
+        .route("/auth/:provider/authorized", get(login_authorized))
+        .route("/auth/authorized", get(legacy_login_authorized))
         .route("/protected", get(protected))
         .route("/logout", get(logout))
+        .layer(middleware::from_fn_with_state(state.clone(), renew_session))
         .layer(TraceLayer::new_for_http())
         .with_state(state);
     let port = env::var("PORT").unwrap_or("8000".to_string());
     let address = format!("0.0.0.0:{port}");
     let listener = tokio::net::TcpListener::bind(&address).await.unwrap();
 
-    axum::serve(listener, app).await.unwrap();
+    // Connection info is where a session's IP address comes from when running locally
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
     Ok(())
 }
@@ -0,0 +1,241 @@
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use crate::{
+    database::Database,
+    error::{ApiError, ApiResult},
+};
+
+// Provider name of username/password logins, whose account id is the username
+pub const PASSWORD_PROVIDER: &str = "local";
+// Provider name of emailed login links, whose account id is the email address
+pub const EMAIL_PROVIDER: &str = "email";
+
+// An identity at a login provider, linked to one of our users
+#[derive(FromRow)]
+pub struct Account {
+    pub id: String,
+    pub provider: String,
+    pub user_id: Uuid,
+}
+
+#[derive(FromRow)]
+pub struct PasswordLogin {
+    pub id: String,
+    pub user_id: Uuid,
+    pub password_hash: String,
+}
+
+pub trait AccountDataLayer {
+    async fn get_accounts<'a>(&'a self, user_id: &'a Uuid) -> ApiResult<Vec<Account>>;
+    async fn get_account<'a>(
+        &'a self,
+        provider: &'a str,
+        id: &'a str,
+    ) -> ApiResult<Option<Account>>;
+    async fn link_account<'a>(
+        &'a self,
+        user_id: &'a Uuid,
+        provider: &'a str,
+        id: &'a str,
+    ) -> ApiResult<()>;
+    async fn unlink_account<'a>(
+        &'a self,
+        user_id: &'a Uuid,
+        provider: &'a str,
+        id: &'a str,
+    ) -> ApiResult<()>;
+    async fn get_password_login<'a>(
+        &'a self,
+        username: &'a str,
+    ) -> ApiResult<Option<PasswordLogin>>;
+    async fn get_user_password_login<'a>(
+        &'a self,
+        user_id: &'a Uuid,
+    ) -> ApiResult<Option<PasswordLogin>>;
+    async fn add_password_login<'a>(
+        &'a self,
+        user_id: &'a Uuid,
+        username: &'a str,
+        password_hash: &'a str,
+    ) -> ApiResult<()>;
+    async fn set_password<'a>(&'a self, user_id: &'a Uuid, password_hash: &'a str)
+        -> ApiResult<()>;
+}
+
+impl AccountDataLayer for Database {
+    async fn get_accounts<'a>(&'a self, user_id: &'a Uuid) -> ApiResult<Vec<Account>> {
+        let accounts = sqlx::query_as::<_, Account>(
+            r#"SELECT id, provider, user_id FROM account WHERE user_id = ? ORDER BY provider, id"#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await?;
+        Ok(accounts)
+    }
+
+    async fn get_account<'a>(
+        &'a self,
+        provider: &'a str,
+        id: &'a str,
+    ) -> ApiResult<Option<Account>> {
+        let account = sqlx::query_as::<_, Account>(
+            r#"SELECT id, provider, user_id FROM account WHERE provider = ? AND id = ?"#,
+        )
+        .bind(provider)
+        .bind(id)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(account)
+    }
+
+    async fn link_account<'a>(
+        &'a self,
+        user_id: &'a Uuid,
+        provider: &'a str,
+        id: &'a str,
+    ) -> ApiResult<()> {
+        let mut trx = self.db.begin().await?;
+
+        let existing = sqlx::query_as::<_, Account>(
+            r#"SELECT id, provider, user_id FROM account WHERE provider = ? AND id = ?"#,
+        )
+        .bind(provider)
+        .bind(id)
+        .fetch_optional(&mut *trx)
+        .await?;
+
+        match existing {
+            // Already linked, nothing to do
+            Some(account) if account.user_id == *user_id => return Ok(()),
+            // Never move an identity between users, that would merge two accounts
+            Some(_) => {
+                return Err(ApiError::Forbidden(
+                    "That login is already linked to a different account".to_string(),
+                ))
+            }
+            None => {}
+        }
+
+        sqlx::query(r#"INSERT INTO account (id, provider, user_id) VALUES ($1, $2, $3)"#)
+            .bind(id)
+            .bind(provider)
+            .bind(user_id)
+            .execute(&mut *trx)
+            .await?;
+
+        trx.commit().await?;
+        Ok(())
+    }
+
+    async fn unlink_account<'a>(
+        &'a self,
+        user_id: &'a Uuid,
+        provider: &'a str,
+        id: &'a str,
+    ) -> ApiResult<()> {
+        let mut trx = self.db.begin().await?;
+
+        let (count,): (i64,) = sqlx::query_as(r#"SELECT COUNT(*) FROM account WHERE user_id = ?"#)
+            .bind(user_id)
+            .fetch_one(&mut *trx)
+            .await?;
+        if count <= 1 {
+            return Err(ApiError::BadRequest(
+                "You can't remove your only way to log in".to_string(),
+            ));
+        }
+
+        let deleted =
+            sqlx::query(r#"DELETE FROM account WHERE user_id = ? AND provider = ? AND id = ?"#)
+                .bind(user_id)
+                .bind(provider)
+                .bind(id)
+                .execute(&mut *trx)
+                .await?;
+        if deleted.rows_affected() == 0 {
+            return Err(ApiError::NotFound);
+        }
+
+        trx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_password_login<'a>(
+        &'a self,
+        username: &'a str,
+    ) -> ApiResult<Option<PasswordLogin>> {
+        let login = sqlx::query_as::<_, PasswordLogin>(
+            r#"SELECT id, user_id, password_hash FROM account WHERE provider = ? AND id = ? AND password_hash IS NOT NULL"#,
+        )
+        .bind(PASSWORD_PROVIDER)
+        .bind(username)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(login)
+    }
+
+    async fn get_user_password_login<'a>(
+        &'a self,
+        user_id: &'a Uuid,
+    ) -> ApiResult<Option<PasswordLogin>> {
+        let login = sqlx::query_as::<_, PasswordLogin>(
+            r#"SELECT id, user_id, password_hash FROM account WHERE provider = ? AND user_id = ? AND password_hash IS NOT NULL"#,
+        )
+        .bind(PASSWORD_PROVIDER)
+        .bind(user_id)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(login)
+    }
+
+    async fn add_password_login<'a>(
+        &'a self,
+        user_id: &'a Uuid,
+        username: &'a str,
+        password_hash: &'a str,
+    ) -> ApiResult<()> {
+        let mut trx = self.db.begin().await?;
+
+        let (taken,): (i64,) = sqlx::query_as(
+            r#"SELECT COUNT(*) FROM account WHERE provider = ? AND (id = ? OR user_id = ?)"#,
+        )
+        .bind(PASSWORD_PROVIDER)
+        .bind(username)
+        .bind(user_id)
+        .fetch_one(&mut *trx)
+        .await?;
+        if taken > 0 {
+            return Err(ApiError::BadRequest(
+                "That username is already taken".to_string(),
+            ));
+        }
+
+        sqlx::query(
+            r#"INSERT INTO account (id, provider, user_id, password_hash) VALUES ($1, $2, $3, $4)"#,
+        )
+        .bind(username)
+        .bind(PASSWORD_PROVIDER)
+        .bind(user_id)
+        .bind(password_hash)
+        .execute(&mut *trx)
+        .await?;
+
+        trx.commit().await?;
+        Ok(())
+    }
+
+    async fn set_password<'a>(
+        &'a self,
+        user_id: &'a Uuid,
+        password_hash: &'a str,
+    ) -> ApiResult<()> {
+        sqlx::query(r#"UPDATE account SET password_hash = ? WHERE provider = ? AND user_id = ?"#)
+            .bind(password_hash)
+            .bind(PASSWORD_PROVIDER)
+            .bind(user_id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+}
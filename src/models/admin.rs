@@ -0,0 +1,192 @@
+use sqlx::FromRow;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{
+    database::Database,
+    error::{ApiError, ApiResult},
+    models::invites::InviteStatus,
+};
+
+// A user as listed on the admin dashboard
+#[derive(FromRow)]
+pub struct UserSummary {
+    pub id: Uuid,
+    pub name: String,
+    pub created_at: OffsetDateTime,
+    pub disabled_at: Option<OffsetDateTime>,
+    pub is_admin: bool,
+    pub pending: bool,
+    pub routine_count: i64,
+    pub entry_count: i64,
+}
+
+#[derive(FromRow)]
+pub struct InviteSummary {
+    pub id: Uuid,
+    pub status: InviteStatus,
+    pub created_at: OffsetDateTime,
+    pub expires_at: Option<OffsetDateTime>,
+    pub max_uses: i64,
+    pub uses: i64,
+    pub sender_name: String,
+    // Whoever used it last
+    pub accepted_by_name: Option<String>,
+}
+
+impl InviteSummary {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= OffsetDateTime::now_utc())
+    }
+}
+
+#[derive(FromRow)]
+pub struct InstanceTotals {
+    pub users: i64,
+    pub disabled_users: i64,
+    pub pending_users: i64,
+    pub routines: i64,
+    pub entries: i64,
+    pub open_invites: i64,
+    pub active_sessions: i64,
+}
+
+pub trait AdminDataLayer {
+    async fn get_user_summaries(&self) -> ApiResult<Vec<UserSummary>>;
+    async fn get_all_invites(&self) -> ApiResult<Vec<InviteSummary>>;
+    async fn get_instance_totals(&self) -> ApiResult<InstanceTotals>;
+    async fn set_user_disabled<'a>(&'a self, id: &'a Uuid, disabled: bool) -> ApiResult<()>;
+    async fn set_user_admin<'a>(&'a self, id: &'a Uuid, admin: bool) -> ApiResult<()>;
+    async fn approve_user<'a>(&'a self, id: &'a Uuid) -> ApiResult<()>;
+}
+
+impl AdminDataLayer for Database {
+    async fn get_user_summaries(&self) -> ApiResult<Vec<UserSummary>> {
+        let users = sqlx::query_as::<_, UserSummary>(
+            r#"
+            SELECT
+                user.id,
+                user.name,
+                user.created_at,
+                user.disabled_at,
+                user.is_admin,
+                user.pending,
+                (SELECT COUNT(*) FROM routine WHERE routine.user_id = user.id) AS routine_count,
+                (
+                    SELECT COUNT(*) FROM routine_entry
+                    JOIN routine ON routine.id = routine_entry.routine_id
+                    WHERE routine.user_id = user.id
+                ) AS entry_count
+            FROM
+                user
+            ORDER BY
+                user.created_at
+            "#,
+        )
+        .fetch_all(&self.db)
+        .await?;
+        Ok(users)
+    }
+
+    async fn get_all_invites(&self) -> ApiResult<Vec<InviteSummary>> {
+        let invites = sqlx::query_as::<_, InviteSummary>(
+            r#"
+            SELECT
+                invite.id,
+                invite.status,
+                invite.created_at,
+                invite.expires_at,
+                invite.max_uses,
+                invite.uses,
+                sender.name AS sender_name,
+                accepted.name AS accepted_by_name
+            FROM
+                invite
+            JOIN
+                user sender ON sender.id = invite.sender_id
+            LEFT JOIN
+                user accepted ON accepted.id = invite.accepted_by
+            ORDER BY
+                invite.created_at DESC
+            "#,
+        )
+        .fetch_all(&self.db)
+        .await?;
+        Ok(invites)
+    }
+
+    async fn get_instance_totals(&self) -> ApiResult<InstanceTotals> {
+        let totals = sqlx::query_as::<_, InstanceTotals>(
+            r#"
+            SELECT
+                (SELECT COUNT(*) FROM user) AS users,
+                (SELECT COUNT(*) FROM user WHERE disabled_at IS NOT NULL) AS disabled_users,
+                (SELECT COUNT(*) FROM user WHERE pending) AS pending_users,
+                (SELECT COUNT(*) FROM routine) AS routines,
+                (SELECT COUNT(*) FROM routine_entry) AS entries,
+                (
+                    SELECT COUNT(*) FROM invite
+                    WHERE status = $1 AND (expires_at IS NULL OR expires_at > $2)
+                ) AS open_invites,
+                (
+                    SELECT COUNT(*) FROM session
+                    WHERE user_id IS NOT NULL AND expiry > $2
+                ) AS active_sessions
+            "#,
+        )
+        .bind(InviteStatus::Sent)
+        .bind(OffsetDateTime::now_utc())
+        .fetch_one(&self.db)
+        .await?;
+        Ok(totals)
+    }
+
+    async fn set_user_disabled<'a>(&'a self, id: &'a Uuid, disabled: bool) -> ApiResult<()> {
+        let mut trx = self.db.begin().await?;
+        let disabled_at = disabled.then(OffsetDateTime::now_utc);
+        let result = sqlx::query(r#"UPDATE user SET disabled_at = ? WHERE id = ?"#)
+            .bind(disabled_at)
+            .bind(id)
+            .execute(&mut *trx)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(ApiError::NotFound);
+        }
+        // They'd be turned away on their next request anyway, but this frees up the rows
+        if disabled {
+            sqlx::query(r#"DELETE FROM session WHERE user_id = ?"#)
+                .bind(id)
+                .execute(&mut *trx)
+                .await?;
+        }
+        trx.commit().await?;
+        self.users.remove(id);
+        Ok(())
+    }
+
+    async fn set_user_admin<'a>(&'a self, id: &'a Uuid, admin: bool) -> ApiResult<()> {
+        let result = sqlx::query(r#"UPDATE user SET is_admin = ? WHERE id = ?"#)
+            .bind(admin)
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(ApiError::NotFound);
+        }
+        self.users.remove(id);
+        Ok(())
+    }
+
+    async fn approve_user<'a>(&'a self, id: &'a Uuid) -> ApiResult<()> {
+        let result = sqlx::query(r#"UPDATE user SET pending = FALSE WHERE id = ? AND pending"#)
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(ApiError::NotFound);
+        }
+        self.users.remove(id);
+        Ok(())
+    }
+}
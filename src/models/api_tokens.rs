@@ -0,0 +1,142 @@
+use serde::Deserialize;
+use sqlx::FromRow;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{
+    database::Database,
+    error::{ApiError, ApiResult},
+};
+
+#[derive(sqlx::Type, Deserialize, PartialEq, Clone, Copy)]
+pub enum TokenScope {
+    // Only allowed to make requests that don't change anything
+    Read,
+    ReadWrite,
+}
+
+#[derive(FromRow)]
+pub struct ApiToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub scope: TokenScope,
+    pub created_at: OffsetDateTime,
+    pub expires_at: Option<OffsetDateTime>,
+    pub last_used_at: Option<OffsetDateTime>,
+}
+
+impl ApiToken {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= OffsetDateTime::now_utc())
+    }
+}
+
+pub trait ApiTokenDataLayer {
+    async fn get_api_tokens<'a>(&'a self, user_id: &'a Uuid) -> ApiResult<Vec<ApiToken>>;
+    async fn find_api_token<'a>(&'a self, token_hash: &'a str) -> ApiResult<Option<ApiToken>>;
+    async fn create_api_token<'a>(
+        &'a self,
+        user_id: &'a Uuid,
+        name: &'a str,
+        token_hash: &'a str,
+        scope: TokenScope,
+        expires_at: Option<OffsetDateTime>,
+    ) -> ApiResult<()>;
+    async fn delete_api_token<'a>(&'a self, user_id: &'a Uuid, id: &'a Uuid) -> ApiResult<()>;
+    async fn touch_api_token<'a>(&'a self, id: &'a Uuid) -> ApiResult<()>;
+}
+
+impl ApiTokenDataLayer for Database {
+    async fn get_api_tokens<'a>(&'a self, user_id: &'a Uuid) -> ApiResult<Vec<ApiToken>> {
+        let tokens = sqlx::query_as::<_, ApiToken>(
+            r#"
+            SELECT
+                id, user_id, name, scope, created_at, expires_at, last_used_at
+            FROM
+                api_token
+            WHERE
+                user_id = ?
+            ORDER BY
+                created_at
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await?;
+        Ok(tokens)
+    }
+
+    async fn find_api_token<'a>(&'a self, token_hash: &'a str) -> ApiResult<Option<ApiToken>> {
+        let token = sqlx::query_as::<_, ApiToken>(
+            r#"
+            SELECT
+                id, user_id, name, scope, created_at, expires_at, last_used_at
+            FROM
+                api_token
+            WHERE
+                token_hash = ?
+            "#,
+        )
+        .bind(token_hash)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(token)
+    }
+
+    async fn create_api_token<'a>(
+        &'a self,
+        user_id: &'a Uuid,
+        name: &'a str,
+        token_hash: &'a str,
+        scope: TokenScope,
+        expires_at: Option<OffsetDateTime>,
+    ) -> ApiResult<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO api_token (
+                id,
+                user_id,
+                name,
+                token_hash,
+                scope,
+                created_at,
+                expires_at
+            ) VALUES
+                ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(name)
+        .bind(token_hash)
+        .bind(scope)
+        .bind(OffsetDateTime::now_utc())
+        .bind(expires_at)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_api_token<'a>(&'a self, user_id: &'a Uuid, id: &'a Uuid) -> ApiResult<()> {
+        let result = sqlx::query(r#"DELETE FROM api_token WHERE user_id = ? AND id = ?"#)
+            .bind(user_id)
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(ApiError::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn touch_api_token<'a>(&'a self, id: &'a Uuid) -> ApiResult<()> {
+        sqlx::query(r#"UPDATE api_token SET last_used_at = ? WHERE id = ?"#)
+            .bind(OffsetDateTime::now_utc())
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+}
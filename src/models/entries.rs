@@ -1,8 +1,11 @@
-use sqlx::{prelude::FromRow, sqlite::SqliteRow, Row};
+use sqlx::prelude::FromRow;
 use time::Date;
 use uuid::Uuid;
 
-use crate::{database::Database, error::ApiResult};
+use crate::{
+    database::Database,
+    error::{ApiError, ApiResult},
+};
 
 #[derive(FromRow)]
 pub struct RoutineEntry {
@@ -10,29 +13,64 @@ pub struct RoutineEntry {
     pub routine_id: Uuid,
 }
 
+// Every method takes the id of the user making the request, and only touches entries of
+// their own routines. Anyone else's routine is treated as if it doesn't exist
 pub trait RoutineEntryDataLayer {
-    async fn get_entries<'a>(&'a self, routine_id: &'a Vec<Uuid>) -> ApiResult<Vec<RoutineEntry>>;
-    async fn toggle_entries<'a>(&'a self, date: &'a Date, routine_id: &'a Uuid) -> ApiResult<bool>;
-    async fn create_entry<'a>(&'a self, date: &'a Date, routine_id: &'a Uuid) -> ApiResult<()>;
-    async fn delete_entry<'a>(&'a self, date: &'a Date, routine_id: &'a Uuid) -> ApiResult<()>;
+    async fn get_entries<'a>(
+        &'a self,
+        user_id: &'a Uuid,
+        routine_ids: &'a [Uuid],
+    ) -> ApiResult<Vec<RoutineEntry>>;
+    // Returns whether the entry existed before it was toggled
+    async fn toggle_entries<'a>(
+        &'a self,
+        date: &'a Date,
+        routine_id: &'a Uuid,
+        user_id: &'a Uuid,
+    ) -> ApiResult<bool>;
+    async fn create_entry<'a>(
+        &'a self,
+        date: &'a Date,
+        routine_id: &'a Uuid,
+        user_id: &'a Uuid,
+    ) -> ApiResult<()>;
+    async fn delete_entry<'a>(
+        &'a self,
+        date: &'a Date,
+        routine_id: &'a Uuid,
+        user_id: &'a Uuid,
+    ) -> ApiResult<()>;
 }
 
 impl Database {
-    async fn entry_exists(&self, date: &Date, routine_id: &Uuid) -> ApiResult<bool> {
-        let record: SqliteRow = sqlx::query(
-            r#"SELECT COUNT(date) as count FROM routine_entry WHERE date = ? AND routine_id = ?"#,
+    async fn entry_exists(
+        &self,
+        date: &Date,
+        routine_id: &Uuid,
+        user_id: &Uuid,
+    ) -> ApiResult<bool> {
+        let (count,): (i64,) = sqlx::query_as(
+            r#"
+            SELECT COUNT(routine_entry.date) FROM routine_entry
+            JOIN routine ON routine.id = routine_entry.routine_id
+            WHERE routine_entry.date = ? AND routine_entry.routine_id = ? AND routine.user_id = ?
+            "#,
         )
         .bind(date)
         .bind(routine_id)
+        .bind(user_id)
         .fetch_one(&self.db)
         .await?;
-        let count: i64 = record.try_get("count")?;
         Ok(count > 0)
     }
 }
 
 impl RoutineEntryDataLayer for Database {
-    async fn get_entries<'a>(&'a self, routine_ids: &'a Vec<Uuid>) -> ApiResult<Vec<RoutineEntry>> {
+    async fn get_entries<'a>(
+        &'a self,
+        user_id: &'a Uuid,
+        routine_ids: &'a [Uuid],
+    ) -> ApiResult<Vec<RoutineEntry>> {
         if routine_ids.is_empty() {
             return Ok(vec![]);
         }
@@ -40,16 +78,18 @@ impl RoutineEntryDataLayer for Database {
         let sql = format!(
             r#"
             SELECT 
-                routine_id, 
-                date 
+                routine_entry.routine_id, 
+                routine_entry.date 
             FROM 
                 routine_entry 
+            JOIN
+                routine ON routine.id = routine_entry.routine_id
             WHERE 
-                routine_id IN ({})
+                routine.user_id = ? AND routine_entry.routine_id IN ({})
             "#,
             params
         );
-        let mut query = sqlx::query_as::<_, RoutineEntry>(&sql);
+        let mut query = sqlx::query_as::<_, RoutineEntry>(&sql).bind(user_id);
         for id in routine_ids {
             query = query.bind(id);
         }
@@ -57,34 +97,64 @@ impl RoutineEntryDataLayer for Database {
         Ok(routines)
     }
 
-    async fn create_entry<'a>(&'a self, date: &'a Date, routine_id: &'a Uuid) -> ApiResult<()> {
-        sqlx::query(r#"INSERT INTO routine_entry (date, routine_id) VALUES ($1, $2)"#)
-            .bind(date)
-            .bind(routine_id)
-            .execute(&self.db)
-            .await?;
+    async fn create_entry<'a>(
+        &'a self,
+        date: &'a Date,
+        routine_id: &'a Uuid,
+        user_id: &'a Uuid,
+    ) -> ApiResult<()> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO routine_entry (date, routine_id)
+            SELECT $1, id FROM routine WHERE id = $2 AND user_id = $3
+            "#,
+        )
+        .bind(date)
+        .bind(routine_id)
+        .bind(user_id)
+        .execute(&self.db)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(ApiError::NotFound);
+        }
         Ok(())
     }
 
-    async fn delete_entry<'a>(&'a self, date: &'a Date, routine_id: &'a Uuid) -> ApiResult<()> {
-        sqlx::query(r#"delete from routine_entry where date = ? and routine_id = ?"#)
-            .bind(date)
-            .bind(routine_id)
-            .execute(&self.db)
-            .await?;
+    async fn delete_entry<'a>(
+        &'a self,
+        date: &'a Date,
+        routine_id: &'a Uuid,
+        user_id: &'a Uuid,
+    ) -> ApiResult<()> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM routine_entry
+            WHERE date = $1 AND routine_id IN (SELECT id FROM routine WHERE id = $2 AND user_id = $3)
+            "#,
+        )
+        .bind(date)
+        .bind(routine_id)
+        .bind(user_id)
+        .execute(&self.db)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(ApiError::NotFound);
+        }
         Ok(())
     }
 
-    async fn toggle_entries<'a>(&'a self, date: &'a Date, routine_id: &'a Uuid) -> ApiResult<bool> {
-        let exists = self.entry_exists(date, routine_id).await?;
-
-        tracing::info!("{exists}");
+    async fn toggle_entries<'a>(
+        &'a self,
+        date: &'a Date,
+        routine_id: &'a Uuid,
+        user_id: &'a Uuid,
+    ) -> ApiResult<bool> {
+        let exists = self.entry_exists(date, routine_id, user_id).await?;
         if exists {
-            self.delete_entry(date, routine_id).await?;
-            Ok(true)
+            self.delete_entry(date, routine_id, user_id).await?;
         } else {
-            self.create_entry(date, routine_id).await?;
-            Ok(false)
+            self.create_entry(date, routine_id, user_id).await?;
         }
+        Ok(exists)
     }
 }
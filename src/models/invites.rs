@@ -1,12 +1,17 @@
 use sqlx::FromRow;
+use std::collections::HashMap;
 use time::OffsetDateTime;
 use uuid::Uuid;
 
-use crate::{database::Database, error::ApiResult};
+use crate::{
+    database::Database,
+    error::{ApiError, ApiResult},
+};
 
 #[derive(sqlx::Type, PartialEq)]
 pub enum InviteStatus {
     Sent,
+    // Every use has been taken
     Accepted,
     Revoked,
 }
@@ -14,9 +19,30 @@ pub enum InviteStatus {
 #[derive(FromRow)]
 pub struct Invite {
     pub id: Uuid,
-    pub sender_id: Uuid,
     pub status: InviteStatus,
     pub created_at: OffsetDateTime,
+    pub expires_at: Option<OffsetDateTime>,
+    pub max_uses: i64,
+    pub uses: i64,
+}
+
+impl Invite {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= OffsetDateTime::now_utc())
+    }
+
+    // Whether someone could still sign up with it
+    pub fn is_open(&self) -> bool {
+        self.status == InviteStatus::Sent && !self.is_expired()
+    }
+}
+
+// An invite as shown to the user who sent it
+pub struct SentInvite {
+    pub invite: Invite,
+    // Names of everyone who signed up with it
+    pub accepted_by: Vec<String>,
 }
 
 impl From<String> for InviteStatus {
@@ -32,43 +58,161 @@ impl From<String> for InviteStatus {
 
 pub trait InviteDataLayer {
     async fn get_invite<'a>(&'a self, id: &'a Uuid) -> ApiResult<Option<Invite>>;
-    async fn create_invite<'a>(&'a self, sender: &'a Uuid) -> ApiResult<Uuid>;
-    async fn update_invite<'a>(&'a self, id: &'a Uuid, status: InviteStatus) -> ApiResult<()>;
+    async fn get_sent_invites<'a>(&'a self, sender: &'a Uuid) -> ApiResult<Vec<SentInvite>>;
+    // `quota` caps how many open invites the sender can have at once
+    async fn create_invite<'a>(
+        &'a self,
+        sender: &'a Uuid,
+        expires_at: Option<OffsetDateTime>,
+        max_uses: i64,
+        quota: Option<i64>,
+    ) -> ApiResult<Uuid>;
+    // Without a `sender`, anyone's invite can be revoked
+    async fn revoke_invite<'a>(&'a self, id: &'a Uuid, sender: Option<&'a Uuid>) -> ApiResult<()>;
 }
 
 impl InviteDataLayer for Database {
     async fn get_invite<'a>(&'a self, id: &'a Uuid) -> ApiResult<Option<Invite>> {
         let invite = sqlx::query_as::<_, Invite>(
-            r#"SELECT id, sender_id, status, created_at FROM invite WHERE id = ?"#,
+            r#"
+            SELECT
+                id, status, created_at, expires_at, max_uses, uses
+            FROM
+                invite
+            WHERE
+                id = ?
+            "#,
         )
-        .bind(&id)
+        .bind(id)
         .fetch_optional(&self.db)
         .await?;
 
         Ok(invite)
     }
 
-    async fn create_invite<'a>(&'a self, sender: &'a Uuid) -> ApiResult<Uuid> {
-        let id = Uuid::new_v4();
+    async fn get_sent_invites<'a>(&'a self, sender: &'a Uuid) -> ApiResult<Vec<SentInvite>> {
+        let invites = sqlx::query_as::<_, Invite>(
+            r#"
+            SELECT
+                id, status, created_at, expires_at, max_uses, uses
+            FROM
+                invite
+            WHERE
+                sender_id = ?
+            ORDER BY
+                created_at DESC
+            "#,
+        )
+        .bind(sender)
+        .fetch_all(&self.db)
+        .await?;
+
+        let uses: Vec<(Uuid, String)> = sqlx::query_as(
+            r#"
+            SELECT
+                invite_use.invite_id, user.name
+            FROM
+                invite_use
+            JOIN
+                invite ON invite.id = invite_use.invite_id
+            JOIN
+                user ON user.id = invite_use.user_id
+            WHERE
+                invite.sender_id = ?
+            ORDER BY
+                invite_use.accepted_at
+            "#,
+        )
+        .bind(sender)
+        .fetch_all(&self.db)
+        .await?;
+        let mut accepted_by: HashMap<Uuid, Vec<String>> = HashMap::new();
+        for (invite_id, name) in uses {
+            accepted_by.entry(invite_id).or_default().push(name);
+        }
+
+        Ok(invites
+            .into_iter()
+            .map(|invite| SentInvite {
+                accepted_by: accepted_by.remove(&invite.id).unwrap_or_default(),
+                invite,
+            })
+            .collect())
+    }
+
+    async fn create_invite<'a>(
+        &'a self,
+        sender: &'a Uuid,
+        expires_at: Option<OffsetDateTime>,
+        max_uses: i64,
+        quota: Option<i64>,
+    ) -> ApiResult<Uuid> {
+        let mut trx = self.db.begin().await?;
         let now = OffsetDateTime::now_utc();
+
+        if let Some(quota) = quota {
+            let (open,): (i64,) = sqlx::query_as(
+                r#"
+                SELECT COUNT(*) FROM invite
+                WHERE sender_id = $1 AND status = $2 AND (expires_at IS NULL OR expires_at > $3)
+                "#,
+            )
+            .bind(sender)
+            .bind(InviteStatus::Sent)
+            .bind(now)
+            .fetch_one(&mut *trx)
+            .await?;
+            if open >= quota {
+                return Err(ApiError::Forbidden(format!(
+                    "You can only have {quota} open invites at once, revoke one to send another"
+                )));
+            }
+        }
+
+        let id = Uuid::new_v4();
         sqlx::query(
-            r#"INSERT INTO invite (id, sender_id, status, created_at) VALUES ($1, $2, $3, $4) "#,
+            r#"
+            INSERT INTO invite (
+                id,
+                sender_id,
+                status,
+                created_at,
+                expires_at,
+                max_uses
+            ) VALUES
+                ($1, $2, $3, $4, $5, $6)
+            "#,
         )
         .bind(id)
         .bind(sender)
         .bind(InviteStatus::Sent)
         .bind(now)
-        .execute(&self.db)
+        .bind(expires_at)
+        .bind(max_uses)
+        .execute(&mut *trx)
         .await?;
+
+        trx.commit().await?;
         Ok(id)
     }
 
-    async fn update_invite<'a>(&'a self, id: &'a Uuid, status: InviteStatus) -> ApiResult<()> {
-        sqlx::query(r#"UPDATE invite SET status = ? WHERE id = ?"#)
-            .bind(status)
-            .bind(id)
-            .execute(&self.db)
-            .await?;
+    // Only invites that are still waiting to be used can be revoked
+    async fn revoke_invite<'a>(&'a self, id: &'a Uuid, sender: Option<&'a Uuid>) -> ApiResult<()> {
+        let result = sqlx::query(
+            r#"
+            UPDATE invite SET status = $1
+            WHERE id = $2 AND status = $3 AND ($4 IS NULL OR sender_id = $4)
+            "#,
+        )
+        .bind(InviteStatus::Revoked)
+        .bind(id)
+        .bind(InviteStatus::Sent)
+        .bind(sender)
+        .execute(&self.db)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(ApiError::NotFound);
+        }
         Ok(())
     }
 }
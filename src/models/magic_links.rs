@@ -0,0 +1,160 @@
+use sqlx::FromRow;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{database::Database, error::ApiResult};
+
+#[derive(FromRow)]
+pub struct MagicLink {
+    pub email: String,
+    pub invite: Option<Uuid>,
+    pub expires_at: OffsetDateTime,
+}
+
+pub trait MagicLinkDataLayer {
+    async fn create_magic_link<'a>(
+        &'a self,
+        token_hash: &'a str,
+        email: &'a str,
+        invite: Option<&'a Uuid>,
+        ip_address: Option<&'a str>,
+        expires_at: OffsetDateTime,
+    ) -> ApiResult<()>;
+    // Links asked for since `since`, to `email` and from `ip_address`
+    async fn count_magic_links<'a>(
+        &'a self,
+        email: &'a str,
+        ip_address: Option<&'a str>,
+        since: OffsetDateTime,
+    ) -> ApiResult<(i64, i64)>;
+    async fn consume_magic_link<'a>(&'a self, token_hash: &'a str) -> ApiResult<Option<MagicLink>>;
+}
+
+impl MagicLinkDataLayer for Database {
+    async fn create_magic_link<'a>(
+        &'a self,
+        token_hash: &'a str,
+        email: &'a str,
+        invite: Option<&'a Uuid>,
+        ip_address: Option<&'a str>,
+        expires_at: OffsetDateTime,
+    ) -> ApiResult<()> {
+        let now = OffsetDateTime::now_utc();
+        let mut trx = self.db.begin().await?;
+
+        // Expired links can never be used again, so there's nothing to keep them for
+        sqlx::query(r#"DELETE FROM magic_link WHERE expires_at < ?"#)
+            .bind(now)
+            .execute(&mut *trx)
+            .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO magic_link (
+                token_hash,
+                email,
+                invite,
+                ip_address,
+                created_at,
+                expires_at
+            ) VALUES
+                ($1, $2, $3, $4, $5, $6)
+            "#,
+        )
+        .bind(token_hash)
+        .bind(email)
+        .bind(invite)
+        .bind(ip_address)
+        .bind(now)
+        .bind(expires_at)
+        .execute(&mut *trx)
+        .await?;
+
+        trx.commit().await?;
+        Ok(())
+    }
+
+    async fn count_magic_links<'a>(
+        &'a self,
+        email: &'a str,
+        ip_address: Option<&'a str>,
+        since: OffsetDateTime,
+    ) -> ApiResult<(i64, i64)> {
+        let counts = sqlx::query_as(
+            r#"
+            SELECT
+                COALESCE(SUM(email = $1), 0),
+                COALESCE(SUM(ip_address = $2), 0)
+            FROM
+                magic_link
+            WHERE
+                created_at > $3
+            "#,
+        )
+        .bind(email)
+        .bind(ip_address)
+        .bind(since)
+        .fetch_one(&self.db)
+        .await?;
+        Ok(counts)
+    }
+
+    // Marks the link as used in the same statement that reads it, so two requests
+    // racing with the same link can't both log in
+    async fn consume_magic_link<'a>(&'a self, token_hash: &'a str) -> ApiResult<Option<MagicLink>> {
+        let link = sqlx::query_as::<_, MagicLink>(
+            r#"
+            UPDATE
+                magic_link
+            SET
+                consumed_at = $1
+            WHERE
+                token_hash = $2 AND consumed_at IS NULL
+            RETURNING
+                email,
+                invite,
+                expires_at
+            "#,
+        )
+        .bind(OffsetDateTime::now_utc())
+        .bind(token_hash)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(link.filter(|link| link.expires_at > OffsetDateTime::now_utc()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use time::OffsetDateTime;
+
+    use super::MagicLinkDataLayer;
+    use crate::database::test_database;
+
+    #[tokio::test]
+    async fn counts_recent_links_per_email_and_ip_address() {
+        let db = test_database().await;
+        let since = OffsetDateTime::now_utc() - Duration::from_secs(60);
+        let expires_at = OffsetDateTime::now_utc() + Duration::from_secs(60);
+        let links = [
+            ("a", "ada@example.com", Some("10.0.0.1")),
+            ("b", "ada@example.com", Some("10.0.0.2")),
+            ("c", "bob@example.com", Some("10.0.0.1")),
+            ("d", "bob@example.com", None),
+        ];
+        for (hash, email, ip_address) in links {
+            db.create_magic_link(hash, email, None, ip_address, expires_at)
+                .await
+                .unwrap();
+        }
+
+        let counts = db.count_magic_links("ada@example.com", Some("10.0.0.1"), since);
+        assert_eq!(counts.await.unwrap(), (2, 2));
+        let counts = db.count_magic_links("bob@example.com", None, since);
+        assert_eq!(counts.await.unwrap(), (2, 0));
+        let later = OffsetDateTime::now_utc() + Duration::from_secs(1);
+        let counts = db.count_magic_links("ada@example.com", Some("10.0.0.1"), later);
+        assert_eq!(counts.await.unwrap(), (0, 0));
+    }
+}
@@ -1,5 +1,11 @@
+pub mod accounts;
+pub mod admin;
+pub mod api_tokens;
 pub mod entries;
 pub mod invites;
+pub mod magic_links;
+pub mod passkeys;
 pub mod routines;
 pub mod sessions;
+pub mod totp;
 pub mod users;
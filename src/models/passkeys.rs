@@ -0,0 +1,158 @@
+use sqlx::FromRow;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{
+    database::Database,
+    error::{ApiError, ApiResult},
+};
+
+#[derive(FromRow)]
+pub struct Passkey {
+    // Base64url, as the browser reports it
+    pub id: String,
+    pub user_id: Uuid,
+    pub name: String,
+    // COSE encoded
+    pub public_key: Vec<u8>,
+    pub sign_count: i64,
+    pub created_at: OffsetDateTime,
+    pub last_used_at: Option<OffsetDateTime>,
+}
+
+pub trait PasskeyDataLayer {
+    async fn get_passkeys<'a>(&'a self, user_id: &'a Uuid) -> ApiResult<Vec<Passkey>>;
+    async fn get_passkey<'a>(&'a self, id: &'a str) -> ApiResult<Option<Passkey>>;
+    async fn add_passkey<'a>(
+        &'a self,
+        id: &'a str,
+        user_id: &'a Uuid,
+        name: &'a str,
+        public_key: &'a [u8],
+        sign_count: i64,
+    ) -> ApiResult<()>;
+    async fn rename_passkey<'a>(
+        &'a self,
+        user_id: &'a Uuid,
+        id: &'a str,
+        name: &'a str,
+    ) -> ApiResult<()>;
+    async fn delete_passkey<'a>(&'a self, user_id: &'a Uuid, id: &'a str) -> ApiResult<()>;
+    async fn update_passkey_use<'a>(&'a self, id: &'a str, sign_count: i64) -> ApiResult<()>;
+}
+
+impl PasskeyDataLayer for Database {
+    async fn get_passkeys<'a>(&'a self, user_id: &'a Uuid) -> ApiResult<Vec<Passkey>> {
+        let passkeys = sqlx::query_as::<_, Passkey>(
+            r#"
+            SELECT
+                id, user_id, name, public_key, sign_count, created_at, last_used_at
+            FROM
+                passkey
+            WHERE
+                user_id = ?
+            ORDER BY
+                created_at
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await?;
+        Ok(passkeys)
+    }
+
+    async fn get_passkey<'a>(&'a self, id: &'a str) -> ApiResult<Option<Passkey>> {
+        let passkey = sqlx::query_as::<_, Passkey>(
+            r#"
+            SELECT
+                id, user_id, name, public_key, sign_count, created_at, last_used_at
+            FROM
+                passkey
+            WHERE
+                id = ?
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(passkey)
+    }
+
+    async fn add_passkey<'a>(
+        &'a self,
+        id: &'a str,
+        user_id: &'a Uuid,
+        name: &'a str,
+        public_key: &'a [u8],
+        sign_count: i64,
+    ) -> ApiResult<()> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO passkey (
+                id,
+                user_id,
+                name,
+                public_key,
+                sign_count,
+                created_at
+            ) VALUES
+                ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (id) DO NOTHING
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(name)
+        .bind(public_key)
+        .bind(sign_count)
+        .bind(OffsetDateTime::now_utc())
+        .execute(&self.db)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(ApiError::BadRequest(
+                "That passkey is already registered".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    async fn rename_passkey<'a>(
+        &'a self,
+        user_id: &'a Uuid,
+        id: &'a str,
+        name: &'a str,
+    ) -> ApiResult<()> {
+        let result = sqlx::query(r#"UPDATE passkey SET name = ? WHERE user_id = ? AND id = ?"#)
+            .bind(name)
+            .bind(user_id)
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(ApiError::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn delete_passkey<'a>(&'a self, user_id: &'a Uuid, id: &'a str) -> ApiResult<()> {
+        let result = sqlx::query(r#"DELETE FROM passkey WHERE user_id = ? AND id = ?"#)
+            .bind(user_id)
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(ApiError::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn update_passkey_use<'a>(&'a self, id: &'a str, sign_count: i64) -> ApiResult<()> {
+        sqlx::query(r#"UPDATE passkey SET sign_count = ?, last_used_at = ? WHERE id = ?"#)
+            .bind(sign_count)
+            .bind(OffsetDateTime::now_utc())
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+}
@@ -2,7 +2,10 @@ use sqlx::prelude::FromRow;
 use time::{Date, OffsetDateTime};
 use uuid::Uuid;
 
-use crate::{database::Database, error::ApiResult};
+use crate::{
+    database::Database,
+    error::{ApiError, ApiResult},
+};
 
 #[derive(FromRow)]
 pub struct Routine {
@@ -11,40 +14,125 @@ pub struct Routine {
     pub color: String,
     pub user_id: Uuid,
     pub created_at: OffsetDateTime,
-    pub updated_at: Option<OffsetDateTime>,
+    // Archived routines are kept out of the way, on the archived page
+    pub archived_at: Option<OffsetDateTime>,
+    // Set while the routine is paused. Paused days don't count towards or against streaks
+    pub paused_since: Option<Date>,
+}
+
+impl Routine {
+    pub fn is_archived(&self) -> bool {
+        self.archived_at.is_some()
+    }
+
+    // Whether the routine was paused on `date`, now or in one of its earlier pauses
+    pub fn is_paused_on(&self, date: Date, pauses: &[RoutinePause]) -> bool {
+        self.paused_since.is_some_and(|since| date >= since)
+            || pauses.iter().any(|pause| {
+                pause.routine_id == self.id && (pause.started_on..=pause.ended_on).contains(&date)
+            })
+    }
+}
+
+// A pause that has since ended
+#[derive(FromRow)]
+pub struct RoutinePause {
+    pub routine_id: Uuid,
+    pub started_on: Date,
+    pub ended_on: Date,
 }
 
 pub struct RoutineWithEntries {
     pub routine: Routine,
     pub entries: Vec<(Date, bool)>,
+    pub streak: i64,
 }
 
+// Routines are only ever looked up or changed on behalf of their owner, so someone
+// else's routine can't be told apart from one that doesn't exist
 pub trait RoutineDataLayer {
-    async fn get_routine<'a>(&'a self, id: &'a Uuid) -> ApiResult<Option<Routine>>;
-    async fn get_routines<'a>(&'a self, user_id: &'a Uuid) -> ApiResult<Vec<Routine>>;
+    async fn get_routine<'a>(
+        &'a self,
+        id: &'a Uuid,
+        user_id: &'a Uuid,
+    ) -> ApiResult<Option<Routine>>;
+    // All of the user's routines, or only the archived or unarchived ones
+    async fn get_routines<'a>(
+        &'a self,
+        user_id: &'a Uuid,
+        archived: Option<bool>,
+    ) -> ApiResult<Vec<Routine>>;
     async fn create_routine<'a>(
         &'a self,
         title: &'a str,
         color: &'a str,
         user_id: &'a Uuid,
     ) -> ApiResult<Routine>;
-    async fn delete_routine<'a>(&'a self, id: &'a Uuid) -> ApiResult<()>;
+    async fn update_routine<'a>(
+        &'a self,
+        id: &'a Uuid,
+        user_id: &'a Uuid,
+        title: &'a str,
+        color: &'a str,
+    ) -> ApiResult<Routine>;
+    async fn set_routine_archived<'a>(
+        &'a self,
+        id: &'a Uuid,
+        user_id: &'a Uuid,
+        archived: bool,
+    ) -> ApiResult<Routine>;
+    // `today` is the owner's date, in their timezone. Pausing an already paused routine
+    // leaves it paused from the same day
+    async fn pause_routine<'a>(
+        &'a self,
+        id: &'a Uuid,
+        user_id: &'a Uuid,
+        today: Date,
+    ) -> ApiResult<Routine>;
+    async fn resume_routine<'a>(
+        &'a self,
+        id: &'a Uuid,
+        user_id: &'a Uuid,
+        today: Date,
+    ) -> ApiResult<Routine>;
+    async fn get_pauses<'a>(&'a self, user_id: &'a Uuid) -> ApiResult<Vec<RoutinePause>>;
+    // Takes the routine's entries with it
+    async fn delete_routine<'a>(&'a self, id: &'a Uuid, user_id: &'a Uuid) -> ApiResult<()>;
 }
 
 impl RoutineDataLayer for Database {
-    async fn get_routine<'a>(&'a self, id: &'a Uuid) -> ApiResult<Option<Routine>> {
+    async fn get_routine<'a>(
+        &'a self,
+        id: &'a Uuid,
+        user_id: &'a Uuid,
+    ) -> ApiResult<Option<Routine>> {
         let routine = sqlx::query_as::<_, Routine>(
-            r#"SELECT id, title, color, user_id, created_at, updated_at FROM routine WHERE id = ?"#,
+            r#"SELECT id, title, color, user_id, created_at, archived_at, paused_since FROM routine WHERE id = ? AND user_id = ?"#,
         )
         .bind(id)
+        .bind(user_id)
         .fetch_optional(&self.db)
         .await?;
         Ok(routine)
     }
 
-    async fn get_routines<'a>(&'a self, user_id: &'a Uuid) -> ApiResult<Vec<Routine>> {
+    async fn get_routines<'a>(
+        &'a self,
+        user_id: &'a Uuid,
+        archived: Option<bool>,
+    ) -> ApiResult<Vec<Routine>> {
         let routine = sqlx::query_as::<_, Routine>(
-            r#"SELECT id, title, color, user_id, created_at, updated_at FROM routine WHERE user_id = ?"#).bind( user_id)
+            r#"
+            SELECT
+                id, title, color, user_id, created_at, archived_at, paused_since
+            FROM
+                routine
+            WHERE
+                user_id = $1 AND ($2 IS NULL OR (archived_at IS NOT NULL) = $2)
+            "#,
+        )
+        .bind(user_id)
+        .bind(archived)
         .fetch_all(&self.db)
         .await?;
         Ok(routine)
@@ -69,11 +157,329 @@ impl RoutineDataLayer for Database {
         Ok(routine)
     }
 
-    async fn delete_routine<'a>(&'a self, id: &'a Uuid) -> ApiResult<()> {
-        sqlx::query(r#"DELETE FROM routine WHERE id = ?"#)
+    async fn update_routine<'a>(
+        &'a self,
+        id: &'a Uuid,
+        user_id: &'a Uuid,
+        title: &'a str,
+        color: &'a str,
+    ) -> ApiResult<Routine> {
+        let routine = sqlx::query_as::<_, Routine>(
+            r#"
+            UPDATE routine SET
+                title = $1,
+                color = $2,
+                updated_at = $3
+            WHERE
+                id = $4 AND user_id = $5
+            RETURNING
+                id, title, color, user_id, created_at, archived_at, paused_since
+            "#,
+        )
+        .bind(title)
+        .bind(color)
+        .bind(OffsetDateTime::now_utc())
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(&self.db)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+        Ok(routine)
+    }
+
+    async fn set_routine_archived<'a>(
+        &'a self,
+        id: &'a Uuid,
+        user_id: &'a Uuid,
+        archived: bool,
+    ) -> ApiResult<Routine> {
+        let routine = sqlx::query_as::<_, Routine>(
+            r#"
+            UPDATE routine SET
+                archived_at = $1
+            WHERE
+                id = $2 AND user_id = $3
+            RETURNING
+                id, title, color, user_id, created_at, archived_at, paused_since
+            "#,
+        )
+        .bind(archived.then(OffsetDateTime::now_utc))
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(&self.db)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+        Ok(routine)
+    }
+
+    async fn pause_routine<'a>(
+        &'a self,
+        id: &'a Uuid,
+        user_id: &'a Uuid,
+        today: Date,
+    ) -> ApiResult<Routine> {
+        let routine = sqlx::query_as::<_, Routine>(
+            r#"
+            UPDATE routine SET
+                paused_since = COALESCE(paused_since, $1)
+            WHERE
+                id = $2 AND user_id = $3
+            RETURNING
+                id, title, color, user_id, created_at, archived_at, paused_since
+            "#,
+        )
+        .bind(today)
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(&self.db)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+        Ok(routine)
+    }
+
+    // The pause is kept as running up to yesterday, so today counts again
+    async fn resume_routine<'a>(
+        &'a self,
+        id: &'a Uuid,
+        user_id: &'a Uuid,
+        today: Date,
+    ) -> ApiResult<Routine> {
+        let mut trx = self.db.begin().await?;
+        let yesterday = today.previous_day();
+        let (paused_since,): (Option<Date>,) =
+            sqlx::query_as(r#"SELECT paused_since FROM routine WHERE id = ? AND user_id = ?"#)
+                .bind(id)
+                .bind(user_id)
+                .fetch_optional(&mut *trx)
+                .await?
+                .ok_or(ApiError::NotFound)?;
+        if let (Some(started_on), Some(ended_on)) = (paused_since, yesterday) {
+            if started_on <= ended_on {
+                sqlx::query(
+                    r#"INSERT INTO routine_pause (routine_id, started_on, ended_on) VALUES ($1, $2, $3)"#,
+                )
+                .bind(id)
+                .bind(started_on)
+                .bind(ended_on)
+                .execute(&mut *trx)
+                .await?;
+            }
+        }
+
+        let routine = sqlx::query_as::<_, Routine>(
+            r#"
+            UPDATE routine SET
+                paused_since = NULL
+            WHERE
+                id = $1 AND user_id = $2
+            RETURNING
+                id, title, color, user_id, created_at, archived_at, paused_since
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_one(&mut *trx)
+        .await?;
+        trx.commit().await?;
+        Ok(routine)
+    }
+
+    async fn get_pauses<'a>(&'a self, user_id: &'a Uuid) -> ApiResult<Vec<RoutinePause>> {
+        let pauses = sqlx::query_as::<_, RoutinePause>(
+            r#"
+            SELECT
+                routine_pause.routine_id, routine_pause.started_on, routine_pause.ended_on
+            FROM
+                routine_pause
+            JOIN
+                routine ON routine.id = routine_pause.routine_id
+            WHERE
+                routine.user_id = ?
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await?;
+        Ok(pauses)
+    }
+
+    async fn delete_routine<'a>(&'a self, id: &'a Uuid, user_id: &'a Uuid) -> ApiResult<()> {
+        let mut trx = self.db.begin().await?;
+        for table in ["routine_entry", "routine_pause"] {
+            sqlx::query(&format!(
+                r#"
+                DELETE FROM {table} WHERE routine_id IN (
+                    SELECT id FROM routine WHERE id = $1 AND user_id = $2
+                )
+                "#
+            ))
             .bind(id)
-            .execute(&self.db)
+            .bind(user_id)
+            .execute(&mut *trx)
+            .await?;
+        }
+        let result = sqlx::query(r#"DELETE FROM routine WHERE id = ? AND user_id = ?"#)
+            .bind(id)
+            .bind(user_id)
+            .execute(&mut *trx)
             .await?;
+        if result.rows_affected() == 0 {
+            return Err(ApiError::NotFound);
+        }
+        trx.commit().await?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use time::{Date, Month, OffsetDateTime};
+    use uuid::Uuid;
+
+    use super::RoutineDataLayer;
+    use crate::{
+        database::{test_database, Database},
+        error::ApiError,
+        models::entries::RoutineEntryDataLayer,
+    };
+
+    async fn create_user(db: &Database, name: &str) -> Uuid {
+        let id = Uuid::new_v4();
+        sqlx::query(r#"INSERT INTO user (id, name, created_at) VALUES ($1, $2, $3)"#)
+            .bind(id)
+            .bind(name)
+            .bind(OffsetDateTime::now_utc())
+            .execute(&db.db)
+            .await
+            .unwrap();
+        id
+    }
+
+    fn day(n: u8) -> Date {
+        Date::from_calendar_date(2024, Month::March, n).unwrap()
+    }
+
+    // Two users, with one routine belonging to `owner` that was done on the 1st
+    async fn setup() -> (Database, Uuid, Uuid, Uuid) {
+        let db = test_database().await;
+        let owner = create_user(&db, "owner").await;
+        let other = create_user(&db, "other").await;
+        let routine = db.create_routine("Read", "#22c55e", &owner).await.unwrap();
+        db.create_entry(&day(1), &routine.id, &owner).await.unwrap();
+        (db, owner, other, routine.id)
+    }
+
+    async fn entry_dates(db: &Database, user_id: &Uuid, routine_id: &Uuid) -> Vec<Date> {
+        let mut dates: Vec<_> = db
+            .get_entries(user_id, &[*routine_id])
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.date)
+            .collect();
+        dates.sort();
+        dates
+    }
+
+    // The owner's routine is exactly as `setup` left it
+    async fn assert_untouched(db: &Database, owner: &Uuid, routine_id: &Uuid) {
+        let routine = db.get_routine(routine_id, owner).await.unwrap().unwrap();
+        assert_eq!(routine.title, "Read");
+        assert_eq!(routine.color, "#22c55e");
+        assert_eq!(entry_dates(db, owner, routine_id).await, vec![day(1)]);
+    }
+
+    #[tokio::test]
+    async fn other_users_cannot_read_routines() {
+        let (db, owner, other, routine_id) = setup().await;
+
+        assert!(db.get_routine(&routine_id, &other).await.unwrap().is_none());
+        assert!(db.get_routines(&other, None).await.unwrap().is_empty());
+        assert!(entry_dates(&db, &other, &routine_id).await.is_empty());
+
+        assert_untouched(&db, &owner, &routine_id).await;
+    }
+
+    #[tokio::test]
+    async fn other_users_cannot_change_entries() {
+        let (db, owner, other, routine_id) = setup().await;
+
+        // Toggling the day that's done would delete it, and any other day would add one
+        for date in [day(1), day(2)] {
+            let result = db.toggle_entries(&date, &routine_id, &other).await;
+            assert!(matches!(result, Err(ApiError::NotFound)));
+        }
+        let result = db.create_entry(&day(2), &routine_id, &other).await;
+        assert!(matches!(result, Err(ApiError::NotFound)));
+        let result = db.delete_entry(&day(1), &routine_id, &other).await;
+        assert!(matches!(result, Err(ApiError::NotFound)));
+
+        assert_untouched(&db, &owner, &routine_id).await;
+    }
+
+    #[tokio::test]
+    async fn other_users_cannot_update_or_delete_routines() {
+        let (db, owner, other, routine_id) = setup().await;
+
+        let result = db
+            .update_routine(&routine_id, &other, "Renamed", "#000000")
+            .await;
+        assert!(matches!(result, Err(ApiError::NotFound)));
+        let result = db.delete_routine(&routine_id, &other).await;
+        assert!(matches!(result, Err(ApiError::NotFound)));
+
+        assert_untouched(&db, &owner, &routine_id).await;
+    }
+
+    #[tokio::test]
+    async fn owners_can_toggle_their_entries() {
+        let (db, owner, _, routine_id) = setup().await;
+
+        assert!(db
+            .toggle_entries(&day(1), &routine_id, &owner)
+            .await
+            .unwrap());
+        assert!(!db
+            .toggle_entries(&day(2), &routine_id, &owner)
+            .await
+            .unwrap());
+        assert_eq!(entry_dates(&db, &owner, &routine_id).await, vec![day(2)]);
+    }
+
+    #[tokio::test]
+    async fn routines_can_be_listed_by_archived_state() {
+        let (db, owner, _, routine_id) = setup().await;
+        let kept = db.create_routine("Run", "#3b82f6", &owner).await.unwrap();
+        db.set_routine_archived(&routine_id, &owner, true)
+            .await
+            .unwrap();
+
+        let ids = |routines: Vec<super::Routine>| -> Vec<Uuid> {
+            routines.into_iter().map(|r| r.id).collect()
+        };
+        let active = db.get_routines(&owner, Some(false)).await.unwrap();
+        assert_eq!(ids(active), vec![kept.id]);
+        let archived = db.get_routines(&owner, Some(true)).await.unwrap();
+        assert_eq!(ids(archived), vec![routine_id]);
+        assert_eq!(db.get_routines(&owner, None).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn pauses_run_until_the_day_before_resuming() {
+        let (db, owner, _, routine_id) = setup().await;
+
+        db.pause_routine(&routine_id, &owner, day(3)).await.unwrap();
+        let routine = db
+            .resume_routine(&routine_id, &owner, day(5))
+            .await
+            .unwrap();
+        assert!(routine.paused_since.is_none());
+
+        let pauses = db.get_pauses(&owner).await.unwrap();
+        assert_eq!(pauses.len(), 1);
+        assert_eq!((pauses[0].started_on, pauses[0].ended_on), (day(3), day(4)));
+        assert!(!routine.is_paused_on(day(2), &pauses));
+        assert!(routine.is_paused_on(day(4), &pauses));
+        assert!(!routine.is_paused_on(day(5), &pauses));
+    }
+}
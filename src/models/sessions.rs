@@ -1,14 +1,45 @@
 use anyhow::Result;
 use async_trait::async_trait;
 use sqlx::FromRow;
+use time::OffsetDateTime;
+use uuid::Uuid;
 
 use crate::database::Database;
 
+// A logged in session, as listed on the settings page
+#[derive(FromRow)]
+pub struct ActiveSession {
+    pub id: String,
+    pub created_at: OffsetDateTime,
+    pub last_seen_at: OffsetDateTime,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
 #[async_trait]
 pub trait SessionDataLayer {
     async fn get_session(&self, id: &str) -> Result<Option<String>>;
-    async fn insert_session(&self, id: &str, session: &str, expiry: Option<String>) -> Result<()>;
+    async fn insert_session(
+        &self,
+        id: &str,
+        session: &str,
+        user_id: Option<&Uuid>,
+        expiry: Option<OffsetDateTime>,
+    ) -> Result<()>;
+    async fn set_session_client(
+        &self,
+        id: &str,
+        user_agent: Option<&str>,
+        ip_address: Option<&str>,
+    ) -> Result<()>;
+    async fn get_user_sessions(&self, user_id: &Uuid) -> Result<Vec<ActiveSession>>;
+    // Unexpired sessions from `ip_address` that nobody has logged in to
+    async fn count_anonymous_sessions(&self, ip_address: &str) -> Result<i64>;
+    async fn rename_session(&self, id: &str, new_id: &str) -> Result<()>;
     async fn delete_session(&self, id: &str) -> Result<()>;
+    async fn delete_user_session(&self, user_id: &Uuid, id: &str) -> Result<u64>;
+    async fn delete_other_user_sessions(&self, user_id: &Uuid, keep_id: &str) -> Result<u64>;
+    async fn delete_expired_sessions(&self) -> Result<u64>;
     async fn delete_all_sessions(&self) -> Result<()>;
 }
 
@@ -19,19 +50,105 @@ impl SessionDataLayer for Database {
         pub struct SessionRow {
             pub session: String,
         }
-        let session =
-            sqlx::query_as::<_, SessionRow>(r#"SELECT session FROM session WHERE id = ?"#)
-                .bind(id)
-                .fetch_optional(&self.db)
-                .await?;
+        // Every session gets an expiry, so one without is treated as expired too
+        let session = sqlx::query_as::<_, SessionRow>(
+            r#"SELECT session FROM session WHERE id = ? AND expiry > ?"#,
+        )
+        .bind(id)
+        .bind(OffsetDateTime::now_utc())
+        .fetch_optional(&self.db)
+        .await?;
         Ok(session.map(|s| s.session))
     }
 
-    async fn insert_session(&self, id: &str, session: &str, expiry: Option<String>) -> Result<()> {
-        sqlx::query(r#"INSERT INTO session (id, session, expiry) VALUES ($1, $2, $3)"#)
+    async fn insert_session(
+        &self,
+        id: &str,
+        session: &str,
+        user_id: Option<&Uuid>,
+        expiry: Option<OffsetDateTime>,
+    ) -> Result<()> {
+        // Storing a session again, e.g. after changing its data, replaces it. Sessions
+        // are stored again as they're renewed, which counts as being seen
+        let now = OffsetDateTime::now_utc();
+        sqlx::query(
+            r#"
+            INSERT INTO session (
+                id,
+                session,
+                user_id,
+                expiry,
+                created_at,
+                last_seen_at
+            ) VALUES
+                ($1, $2, $3, $4, $5, $5)
+            ON CONFLICT (id) DO UPDATE SET
+                session = excluded.session,
+                user_id = excluded.user_id,
+                expiry = excluded.expiry,
+                last_seen_at = excluded.last_seen_at
+            "#,
+        )
+        .bind(id)
+        .bind(session)
+        .bind(user_id)
+        .bind(expiry)
+        .bind(now)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    async fn set_session_client(
+        &self,
+        id: &str,
+        user_agent: Option<&str>,
+        ip_address: Option<&str>,
+    ) -> Result<()> {
+        sqlx::query(r#"UPDATE session SET user_agent = ?, ip_address = ? WHERE id = ?"#)
+            .bind(user_agent)
+            .bind(ip_address)
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn count_anonymous_sessions(&self, ip_address: &str) -> Result<i64> {
+        let (count,): (i64,) = sqlx::query_as(
+            r#"SELECT COUNT(*) FROM session WHERE user_id IS NULL AND ip_address = ? AND expiry > ?"#,
+        )
+        .bind(ip_address)
+        .bind(OffsetDateTime::now_utc())
+        .fetch_one(&self.db)
+        .await?;
+        Ok(count)
+    }
+
+    async fn get_user_sessions(&self, user_id: &Uuid) -> Result<Vec<ActiveSession>> {
+        let sessions = sqlx::query_as::<_, ActiveSession>(
+            r#"
+            SELECT
+                id, created_at, last_seen_at, user_agent, ip_address
+            FROM
+                session
+            WHERE
+                user_id = ? AND expiry > ?
+            ORDER BY
+                last_seen_at DESC
+            "#,
+        )
+        .bind(user_id)
+        .bind(OffsetDateTime::now_utc())
+        .fetch_all(&self.db)
+        .await?;
+        Ok(sessions)
+    }
+
+    async fn rename_session(&self, id: &str, new_id: &str) -> Result<()> {
+        sqlx::query(r#"UPDATE session SET id = ? WHERE id = ?"#)
+            .bind(new_id)
             .bind(id)
-            .bind(session)
-            .bind(expiry)
             .execute(&self.db)
             .await?;
         Ok(())
@@ -44,6 +161,33 @@ impl SessionDataLayer for Database {
             .await?;
         Ok(())
     }
+
+    async fn delete_user_session(&self, user_id: &Uuid, id: &str) -> Result<u64> {
+        let result = sqlx::query(r#"DELETE FROM session WHERE user_id = ? AND id = ?"#)
+            .bind(user_id)
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn delete_other_user_sessions(&self, user_id: &Uuid, keep_id: &str) -> Result<u64> {
+        let result = sqlx::query(r#"DELETE FROM session WHERE user_id = ? AND id != ?"#)
+            .bind(user_id)
+            .bind(keep_id)
+            .execute(&self.db)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn delete_expired_sessions(&self) -> Result<u64> {
+        let result = sqlx::query(r#"DELETE FROM session WHERE expiry IS NULL OR expiry <= ?"#)
+            .bind(OffsetDateTime::now_utc())
+            .execute(&self.db)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
     async fn delete_all_sessions(&self) -> Result<()> {
         sqlx::query(r#"DELETE FROM session "#)
             .execute(&self.db)
@@ -51,3 +195,45 @@ impl SessionDataLayer for Database {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use time::{Duration, OffsetDateTime};
+    use uuid::Uuid;
+
+    use super::SessionDataLayer;
+    use crate::database::test_database;
+
+    #[tokio::test]
+    async fn counts_unexpired_anonymous_sessions_per_ip_address() {
+        let db = test_database().await;
+        let user_id = Uuid::new_v4();
+        sqlx::query(r#"INSERT INTO user (id, name, created_at) VALUES ($1, $2, $3)"#)
+            .bind(user_id)
+            .bind("someone")
+            .bind(OffsetDateTime::now_utc())
+            .execute(&db.db)
+            .await
+            .unwrap();
+        let live = OffsetDateTime::now_utc() + Duration::minutes(5);
+        let expired = OffsetDateTime::now_utc() - Duration::minutes(5);
+        let sessions = [
+            ("a", None, live, "10.0.0.1"),
+            ("b", None, live, "10.0.0.1"),
+            ("c", None, expired, "10.0.0.1"),
+            ("d", Some(&user_id), live, "10.0.0.1"),
+            ("e", None, live, "10.0.0.2"),
+        ];
+        for (id, user_id, expiry, ip_address) in sessions {
+            db.insert_session(id, "{}", user_id, Some(expiry))
+                .await
+                .unwrap();
+            db.set_session_client(id, None, Some(ip_address))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(db.count_anonymous_sessions("10.0.0.1").await.unwrap(), 2);
+        assert_eq!(db.count_anonymous_sessions("10.0.0.3").await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,306 @@
+use sqlx::{FromRow, Sqlite, Transaction};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{
+    database::Database,
+    error::{ApiError, ApiResult},
+};
+
+// A user's TOTP authenticator, which only guards logins once `enabled_at` is set
+#[derive(FromRow)]
+pub struct Totp {
+    // Base32, as shown to authenticator apps
+    pub secret: String,
+    pub enabled_at: Option<OffsetDateTime>,
+}
+
+impl Totp {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled_at.is_some()
+    }
+}
+
+pub trait TotpDataLayer {
+    async fn get_totp<'a>(&'a self, user_id: &'a Uuid) -> ApiResult<Option<Totp>>;
+    async fn start_totp_enrolment<'a>(
+        &'a self,
+        user_id: &'a Uuid,
+        secret: &'a str,
+    ) -> ApiResult<()>;
+    async fn enable_totp<'a>(
+        &'a self,
+        user_id: &'a Uuid,
+        recovery_code_hashes: &'a [String],
+    ) -> ApiResult<()>;
+    async fn disable_totp<'a>(&'a self, user_id: &'a Uuid) -> ApiResult<()>;
+    async fn use_totp_step<'a>(&'a self, user_id: &'a Uuid, step: i64) -> ApiResult<bool>;
+    async fn start_totp_attempt<'a>(
+        &'a self,
+        user_id: &'a Uuid,
+        max_failures: i64,
+        locked_until: OffsetDateTime,
+    ) -> ApiResult<bool>;
+    async fn clear_totp_failures<'a>(&'a self, user_id: &'a Uuid) -> ApiResult<()>;
+    async fn replace_recovery_codes<'a>(
+        &'a self,
+        user_id: &'a Uuid,
+        recovery_code_hashes: &'a [String],
+    ) -> ApiResult<()>;
+    async fn use_recovery_code<'a>(
+        &'a self,
+        user_id: &'a Uuid,
+        code_hash: &'a str,
+    ) -> ApiResult<bool>;
+    async fn count_recovery_codes<'a>(&'a self, user_id: &'a Uuid) -> ApiResult<i64>;
+}
+
+impl Database {
+    async fn insert_recovery_codes<'a>(
+        trx: &mut Transaction<'_, Sqlite>,
+        user_id: &'a Uuid,
+        recovery_code_hashes: &'a [String],
+    ) -> ApiResult<()> {
+        sqlx::query(r#"DELETE FROM recovery_code WHERE user_id = ?"#)
+            .bind(user_id)
+            .execute(&mut **trx)
+            .await?;
+        for hash in recovery_code_hashes {
+            sqlx::query(r#"INSERT INTO recovery_code (user_id, code_hash) VALUES ($1, $2)"#)
+                .bind(user_id)
+                .bind(hash)
+                .execute(&mut **trx)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+impl TotpDataLayer for Database {
+    async fn get_totp<'a>(&'a self, user_id: &'a Uuid) -> ApiResult<Option<Totp>> {
+        let totp =
+            sqlx::query_as::<_, Totp>(r#"SELECT secret, enabled_at FROM totp WHERE user_id = ?"#)
+                .bind(user_id)
+                .fetch_optional(&self.db)
+                .await?;
+        Ok(totp)
+    }
+
+    // Starting again replaces any unfinished enrolment, but never an enabled one
+    async fn start_totp_enrolment<'a>(
+        &'a self,
+        user_id: &'a Uuid,
+        secret: &'a str,
+    ) -> ApiResult<()> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO totp (
+                user_id,
+                secret,
+                created_at
+            ) VALUES
+                ($1, $2, $3)
+            ON CONFLICT (user_id) DO UPDATE SET
+                secret = excluded.secret,
+                created_at = excluded.created_at,
+                last_used_step = NULL
+            WHERE
+                totp.enabled_at IS NULL
+            "#,
+        )
+        .bind(user_id)
+        .bind(secret)
+        .bind(OffsetDateTime::now_utc())
+        .execute(&self.db)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(ApiError::BadRequest(
+                "Two-factor authentication is already on".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    async fn enable_totp<'a>(
+        &'a self,
+        user_id: &'a Uuid,
+        recovery_code_hashes: &'a [String],
+    ) -> ApiResult<()> {
+        let mut trx = self.db.begin().await?;
+
+        let result = sqlx::query(
+            r#"UPDATE totp SET enabled_at = ? WHERE user_id = ? AND enabled_at IS NULL"#,
+        )
+        .bind(OffsetDateTime::now_utc())
+        .bind(user_id)
+        .execute(&mut *trx)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(ApiError::BadRequest(
+                "Start setting up two-factor authentication first".to_string(),
+            ));
+        }
+        Self::insert_recovery_codes(&mut trx, user_id, recovery_code_hashes).await?;
+
+        trx.commit().await?;
+        Ok(())
+    }
+
+    async fn disable_totp<'a>(&'a self, user_id: &'a Uuid) -> ApiResult<()> {
+        let mut trx = self.db.begin().await?;
+        sqlx::query(r#"DELETE FROM recovery_code WHERE user_id = ?"#)
+            .bind(user_id)
+            .execute(&mut *trx)
+            .await?;
+        sqlx::query(r#"DELETE FROM totp WHERE user_id = ?"#)
+            .bind(user_id)
+            .execute(&mut *trx)
+            .await?;
+        trx.commit().await?;
+        Ok(())
+    }
+
+    // Record the time step a code was accepted for, failing if it (or a later
+    // one) was already used
+    async fn use_totp_step<'a>(&'a self, user_id: &'a Uuid, step: i64) -> ApiResult<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE
+                totp
+            SET
+                last_used_step = $1
+            WHERE
+                user_id = $2 AND (last_used_step IS NULL OR last_used_step < $1)
+            "#,
+        )
+        .bind(step)
+        .bind(user_id)
+        .execute(&self.db)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    // Count a code before it's checked, so parallel guesses can't get past the limit,
+    // failing if the user is locked out. Reaching `max_failures` locks them out until
+    // `locked_until`, and every wrong code after that does again, until a right one
+    async fn start_totp_attempt<'a>(
+        &'a self,
+        user_id: &'a Uuid,
+        max_failures: i64,
+        locked_until: OffsetDateTime,
+    ) -> ApiResult<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE
+                totp
+            SET
+                failed_attempts = failed_attempts + 1,
+                locked_until = CASE
+                    WHEN failed_attempts + 1 >= $1 THEN $2
+                    ELSE locked_until
+                END
+            WHERE
+                user_id = $3 AND (locked_until IS NULL OR locked_until <= $4)
+            "#,
+        )
+        .bind(max_failures)
+        .bind(locked_until)
+        .bind(user_id)
+        .bind(OffsetDateTime::now_utc())
+        .execute(&self.db)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn clear_totp_failures<'a>(&'a self, user_id: &'a Uuid) -> ApiResult<()> {
+        sqlx::query(
+            r#"UPDATE totp SET failed_attempts = 0, locked_until = NULL WHERE user_id = ?"#,
+        )
+        .bind(user_id)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    async fn replace_recovery_codes<'a>(
+        &'a self,
+        user_id: &'a Uuid,
+        recovery_code_hashes: &'a [String],
+    ) -> ApiResult<()> {
+        let mut trx = self.db.begin().await?;
+        Self::insert_recovery_codes(&mut trx, user_id, recovery_code_hashes).await?;
+        trx.commit().await?;
+        Ok(())
+    }
+
+    async fn use_recovery_code<'a>(
+        &'a self,
+        user_id: &'a Uuid,
+        code_hash: &'a str,
+    ) -> ApiResult<bool> {
+        let result =
+            sqlx::query(r#"DELETE FROM recovery_code WHERE user_id = ? AND code_hash = ?"#)
+                .bind(user_id)
+                .bind(code_hash)
+                .execute(&self.db)
+                .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn count_recovery_codes<'a>(&'a self, user_id: &'a Uuid) -> ApiResult<i64> {
+        let (count,): (i64,) =
+            sqlx::query_as(r#"SELECT COUNT(*) FROM recovery_code WHERE user_id = ?"#)
+                .bind(user_id)
+                .fetch_one(&self.db)
+                .await?;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use time::OffsetDateTime;
+
+    use super::TotpDataLayer;
+    use crate::{
+        auth::UserResponse,
+        database::test_database,
+        models::users::{RegistrationMode, UserDataLayer},
+    };
+
+    #[tokio::test]
+    async fn locks_users_out_after_too_many_codes() {
+        let db = test_database().await;
+        let login = UserResponse {
+            sub: "user".to_string(),
+            email: None,
+            email_verified: false,
+            name: "user".to_string(),
+        };
+        let user = db
+            .upsert_user("github", &login, None, RegistrationMode::Open)
+            .await
+            .unwrap();
+        db.start_totp_enrolment(&user.id, "SECRET").await.unwrap();
+
+        let later = OffsetDateTime::now_utc() + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(db.start_totp_attempt(&user.id, 3, later).await.unwrap());
+        }
+        assert!(!db.start_totp_attempt(&user.id, 3, later).await.unwrap());
+
+        // Once the lockout is over there's one more try before it starts again
+        let past = OffsetDateTime::now_utc() - Duration::from_secs(60);
+        sqlx::query("UPDATE totp SET locked_until = ?")
+            .bind(past)
+            .execute(&db.db)
+            .await
+            .unwrap();
+        assert!(db.start_totp_attempt(&user.id, 3, later).await.unwrap());
+        assert!(!db.start_totp_attempt(&user.id, 3, later).await.unwrap());
+
+        db.clear_totp_failures(&user.id).await.unwrap();
+        assert!(db.start_totp_attempt(&user.id, 3, later).await.unwrap());
+    }
+}
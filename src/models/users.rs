@@ -1,113 +1,691 @@
+use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
-use time::OffsetDateTime;
+use sqlx::{FromRow, Sqlite, Transaction};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use time::{Date, OffsetDateTime};
+use time_tz::{timezones, OffsetDateTimeExt};
 use uuid::Uuid;
 
-use crate::{auth::UserResponse, database::Database, error::ApiResult};
+use crate::{
+    auth::UserResponse,
+    database::Database,
+    error::{ApiError, ApiResult},
+    models::{
+        accounts::{EMAIL_PROVIDER, PASSWORD_PROVIDER},
+        invites::InviteStatus,
+    },
+};
 
-#[derive(FromRow, Serialize, Deserialize)]
+// Every request made with a session looks up its user, so they're kept around
+// briefly. Changes made through the data layer drop the user straight away
+const USER_CACHE_TTL: Duration = Duration::from_secs(30);
+const USER_CACHE_SIZE: usize = 1024;
+
+// Everything else a user owns, cleared before the user row itself. Foreign keys aren't
+// enforced on the pool, so nothing can be left to cascade
+const USER_DATA_DELETES: &[&str] = &[
+    r#"DELETE FROM routine_entry WHERE routine_id IN (SELECT id FROM routine WHERE user_id = $1)"#,
+    r#"DELETE FROM routine_pause WHERE routine_id IN (SELECT id FROM routine WHERE user_id = $1)"#,
+    r#"DELETE FROM routine WHERE user_id = $1"#,
+    r#"DELETE FROM invite_use WHERE user_id = $1 OR invite_id IN (SELECT id FROM invite WHERE sender_id = $1)"#,
+    // Invites other people sent them stay with their senders
+    r#"UPDATE invite SET accepted_by = NULL WHERE accepted_by = $1"#,
+    r#"DELETE FROM invite WHERE sender_id = $1"#,
+    r#"DELETE FROM api_token WHERE user_id = $1"#,
+    r#"DELETE FROM passkey WHERE user_id = $1"#,
+    r#"DELETE FROM recovery_code WHERE user_id = $1"#,
+    r#"DELETE FROM totp WHERE user_id = $1"#,
+    r#"DELETE FROM session WHERE user_id = $1"#,
+    r#"DELETE FROM account WHERE user_id = $1"#,
+];
+
+#[derive(FromRow, Serialize, Deserialize, Clone)]
 pub struct User {
     pub id: Uuid,
     pub name: String,
     pub created_at: OffsetDateTime,
     pub updated_at: Option<OffsetDateTime>,
+    pub disabled_at: Option<OffsetDateTime>,
+    // Can manage every user and invite from `/admin`
+    pub is_admin: bool,
+    // Signed up while registrations needed approving, and hasn't been approved yet
+    pub pending: bool,
+    pub email: Option<String>,
+    // Set when a login provider vouched for `email`, and cleared when it's edited
+    pub email_verified: bool,
+    // IANA name, e.g. `Europe/London`
+    pub timezone: String,
+    pub week_start: WeekStart,
+    // BCP 47 language tag, e.g. `en-GB`
+    pub locale: String,
+}
+
+#[derive(sqlx::Type, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum WeekStart {
+    Monday,
+    Saturday,
+    Sunday,
+}
+
+impl WeekStart {
+    pub const ALL: [Self; 3] = [Self::Monday, Self::Saturday, Self::Sunday];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Monday => "Monday",
+            Self::Saturday => "Saturday",
+            Self::Sunday => "Sunday",
+        }
+    }
+}
+
+// The editable parts of a user, from the profile settings
+pub struct Profile<'a> {
+    pub name: &'a str,
+    pub email: Option<&'a str>,
+    pub timezone: &'a str,
+    pub week_start: WeekStart,
+    pub locale: &'a str,
+}
+
+impl User {
+    pub fn is_disabled(&self) -> bool {
+        self.disabled_at.is_some()
+    }
+
+    // The date where the user is, which is what streaks and pauses go by. A timezone
+    // that's no longer in the database falls back to UTC
+    pub fn today(&self) -> Date {
+        let now = OffsetDateTime::now_utc();
+        match timezones::get_by_name(&self.timezone) {
+            Some(tz) => now.to_timezone(tz).date(),
+            None => now.date(),
+        }
+    }
+}
+
+// Who can create an account on this instance
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum RegistrationMode {
+    // Anyone can sign up
+    Open,
+    // Only people with an invite can sign up
+    Invite,
+    // Nobody new can sign up, even with an invite
+    Closed,
+    // Anyone can sign up, but needs an admin to approve them unless they had an invite
+    Approval,
+}
+
+impl RegistrationMode {
+    // Whether someone without an invite can sign up at all
+    pub fn allows_uninvited(self) -> bool {
+        matches!(self, Self::Open | Self::Approval)
+    }
+
+    // Until someone has signed up there's nobody to invite or approve anyone, so the
+    // first account can be made without either. Closed still means closed
+    pub fn for_instance(self, has_users: bool) -> Self {
+        match self {
+            Self::Invite | Self::Approval if !has_users => Self::Open,
+            mode => mode,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct UserCache {
+    users: Arc<Mutex<HashMap<Uuid, (Instant, User)>>>,
+}
+
+impl fmt::Debug for UserCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserCache").finish_non_exhaustive()
+    }
+}
+
+impl UserCache {
+    fn get(&self, id: &Uuid) -> Option<User> {
+        let users = self.users.lock().unwrap();
+        users
+            .get(id)
+            .filter(|(loaded_at, _)| loaded_at.elapsed() < USER_CACHE_TTL)
+            .map(|(_, user)| user.clone())
+    }
+
+    fn insert(&self, user: &User) {
+        let mut users = self.users.lock().unwrap();
+        if users.len() >= USER_CACHE_SIZE {
+            users.retain(|_, (loaded_at, _)| loaded_at.elapsed() < USER_CACHE_TTL);
+        }
+        if users.len() < USER_CACHE_SIZE {
+            users.insert(user.id, (Instant::now(), user.clone()));
+        }
+    }
+
+    pub fn remove(&self, id: &Uuid) {
+        self.users.lock().unwrap().remove(id);
+    }
 }
 
 pub trait UserDataLayer {
     async fn get_user<'a>(&'a self, id: &'a Uuid) -> ApiResult<Option<User>>;
-    async fn upsert_user<'a>(&'a self, response: &'a UserResponse) -> ApiResult<User>;
+    async fn has_users(&self) -> ApiResult<bool>;
+    async fn upsert_user<'a>(
+        &'a self,
+        provider: &'a str,
+        response: &'a UserResponse,
+        invite: Option<&'a Uuid>,
+        registration: RegistrationMode,
+    ) -> ApiResult<User>;
+    async fn register_password_user<'a>(
+        &'a self,
+        username: &'a str,
+        name: &'a str,
+        password_hash: &'a str,
+        invite: Option<&'a Uuid>,
+        registration: RegistrationMode,
+    ) -> ApiResult<User>;
+    async fn update_profile<'a>(
+        &'a self,
+        id: &'a Uuid,
+        profile: &'a Profile<'a>,
+    ) -> ApiResult<User>;
     async fn delete_user<'a>(&'a self, id: &'a Uuid) -> ApiResult<()>;
 }
 
 impl Database {
-    #[allow(dead_code)]
-    async fn create_user<'a>(&'a self, name: &'a str) -> ApiResult<Uuid> {
+    // Create a user along with their first login, claiming the invite that let them sign up.
+    // The first user is whoever set the instance up, so they start as its admin
+    async fn create_user<'a>(
+        trx: &mut Transaction<'_, Sqlite>,
+        name: &'a str,
+        provider: &'a str,
+        account_id: &'a str,
+        password_hash: Option<&'a str>,
+        invite: Option<&'a Uuid>,
+        registration: RegistrationMode,
+    ) -> ApiResult<User> {
+        let (has_users,): (bool,) = sqlx::query_as(r#"SELECT EXISTS (SELECT 1 FROM user)"#)
+            .fetch_one(&mut **trx)
+            .await?;
+        let registration = registration.for_instance(has_users);
+        match (registration, invite) {
+            (RegistrationMode::Closed, _) => {
+                return Err(ApiError::Forbidden(
+                    "Not currently accepting new sign ups".to_string(),
+                ))
+            }
+            (RegistrationMode::Invite, None) => {
+                return Err(ApiError::Forbidden(
+                    "An invite link is required to sign up".to_string(),
+                ))
+            }
+            _ => {}
+        }
+
         let id = Uuid::new_v4();
         let now = OffsetDateTime::now_utc();
-        sqlx::query(r#"INSERT INTO user (id, name, created_at) VALUES ($1, $2, $3) RETURNING id, name, created_at, updated_at"#)
-            .bind(id)
-            .bind(name)
-            .bind(now)
-            .execute(&self.db)
-            .await?;
-        Ok(id)
+        let mut user = sqlx::query_as::<_, User>(
+            r#"
+            INSERT INTO user (
+                id,
+                name,
+                created_at,
+                is_admin
+            ) VALUES 
+                ($1, $2, $3, $4) 
+            RETURNING 
+                id,
+                name,
+                created_at,
+                updated_at,
+                disabled_at,
+                is_admin,
+                pending,
+                email,
+                email_verified,
+                timezone,
+                week_start,
+                locale
+            "#,
+        )
+        .bind(id)
+        .bind(name)
+        .bind(now)
+        .bind(!has_users)
+        .fetch_one(&mut **trx)
+        .await?;
+
+        sqlx::query(
+            r#"INSERT INTO account (id, provider, user_id, password_hash) VALUES ($1, $2, $3, $4)"#,
+        )
+        .bind(account_id)
+        .bind(provider)
+        .bind(user.id)
+        .bind(password_hash)
+        .execute(&mut **trx)
+        .await?;
+
+        let invited = match invite {
+            Some(invite) => Self::claim_invite(trx, invite, &user.id, now).await?,
+            None => false,
+        };
+        // Outside of invite-only mode, a stale invite link is just ignored
+        if registration == RegistrationMode::Invite && !invited {
+            return Err(ApiError::Forbidden(
+                "This invite is no longer valid".to_string(),
+            ));
+        }
+
+        // Someone with an invite has been vouched for, so skips the queue
+        if registration == RegistrationMode::Approval && !invited {
+            sqlx::query(r#"UPDATE user SET pending = TRUE WHERE id = ?"#)
+                .bind(user.id)
+                .execute(&mut **trx)
+                .await?;
+            user.pending = true;
+        }
+
+        Ok(user)
+    }
+
+    // Store an address a login provider has verified, unless the user has already set a
+    // different one themselves. If it's the one they set, it's now verified
+    async fn add_verified_email<'a>(
+        trx: &mut Transaction<'_, Sqlite>,
+        id: &'a Uuid,
+        email: &'a str,
+    ) -> ApiResult<User> {
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            UPDATE user SET
+                email = COALESCE(email, $1),
+                email_verified = CASE
+                    WHEN email IS NULL OR lower(email) = lower($1) THEN TRUE
+                    ELSE email_verified
+                END
+            WHERE
+                id = $2
+            RETURNING
+                id,
+                name,
+                created_at,
+                updated_at,
+                disabled_at,
+                is_admin,
+                pending,
+                email,
+                email_verified,
+                timezone,
+                week_start,
+                locale
+            "#,
+        )
+        .bind(email)
+        .bind(id)
+        .fetch_one(&mut **trx)
+        .await?;
+        Ok(user)
+    }
+
+    // Take a use of an invite for a new user. Returns false if it's been used up, expired or
+    // been revoked in the meantime. Taking the last use closes it
+    async fn claim_invite<'a>(
+        trx: &mut Transaction<'_, Sqlite>,
+        invite: &'a Uuid,
+        user_id: &'a Uuid,
+        now: OffsetDateTime,
+    ) -> ApiResult<bool> {
+        let accepted = sqlx::query(
+            r#"
+            UPDATE invite SET
+                uses = uses + 1,
+                accepted_by = $1,
+                status = CASE WHEN uses + 1 >= max_uses THEN $2 ELSE status END
+            WHERE
+                id = $3 AND status = $4 AND (expires_at IS NULL OR expires_at > $5)
+            "#,
+        )
+        .bind(user_id)
+        .bind(InviteStatus::Accepted)
+        .bind(invite)
+        .bind(InviteStatus::Sent)
+        .bind(now)
+        .execute(&mut **trx)
+        .await?;
+        if accepted.rows_affected() == 0 {
+            return Ok(false);
+        }
+        sqlx::query(
+            r#"INSERT INTO invite_use (invite_id, user_id, accepted_at) VALUES ($1, $2, $3)"#,
+        )
+        .bind(invite)
+        .bind(user_id)
+        .bind(now)
+        .execute(&mut **trx)
+        .await?;
+        Ok(true)
     }
 }
 
 impl UserDataLayer for Database {
     async fn get_user<'a>(&'a self, id: &'a Uuid) -> ApiResult<Option<User>> {
+        if let Some(user) = self.users.get(id) {
+            return Ok(Some(user));
+        }
         let user = sqlx::query_as::<_, User>(
-            r#"SELECT id, name, created_at, updated_at FROM user WHERE id = ?"#,
+            r#"
+            SELECT
+                id, name, created_at, updated_at, disabled_at, is_admin, pending,
+                email, email_verified, timezone, week_start, locale
+            FROM
+                user
+            WHERE
+                id = ?
+            "#,
         )
         .bind(id)
         .fetch_optional(&self.db)
         .await?;
+        if let Some(user) = &user {
+            self.users.insert(user);
+        }
         Ok(user)
     }
 
-    async fn upsert_user<'a>(&'a self, response: &'a UserResponse) -> ApiResult<User> {
+    async fn has_users(&self) -> ApiResult<bool> {
+        let (has_users,): (bool,) = sqlx::query_as(r#"SELECT EXISTS (SELECT 1 FROM user)"#)
+            .fetch_one(&self.db)
+            .await?;
+        Ok(has_users)
+    }
+
+    async fn upsert_user<'a>(
+        &'a self,
+        provider: &'a str,
+        response: &'a UserResponse,
+        invite: Option<&'a Uuid>,
+        registration: RegistrationMode,
+    ) -> ApiResult<User> {
         let mut trx = self.db.begin().await?;
 
         let user = sqlx::query_as::<_, User>(
             r#"
             SELECT 
-                user.id, name, created_at, updated_at 
+                user.id, name, created_at, updated_at, disabled_at, is_admin, pending,
+                email, email_verified, timezone, week_start, locale
             FROM 
                 user 
-            LEFT JOIN 
-                account 
+            JOIN 
+                account ON account.user_id = user.id
             WHERE 
-                account.id = ?
+                account.id = ? AND account.provider = ?
             "#,
         )
         .bind(&response.sub)
+        .bind(provider)
         .fetch_optional(&mut *trx)
         .await?;
 
-        if let Some(user) = user {
-            trx.commit().await?;
-            return Ok(user);
+        let user = match user {
+            Some(user) => user,
+            None => {
+                Self::create_user(
+                    &mut trx,
+                    &response.name,
+                    provider,
+                    &response.sub,
+                    None,
+                    invite,
+                    registration,
+                )
+                .await?
+            }
+        };
+        let user = match (&response.email, response.email_verified) {
+            (Some(email), true) => Self::add_verified_email(&mut trx, &user.id, email).await?,
+            _ => user,
+        };
+
+        trx.commit().await?;
+        self.users.remove(&user.id);
+        Ok(user)
+    }
+
+    async fn register_password_user<'a>(
+        &'a self,
+        username: &'a str,
+        name: &'a str,
+        password_hash: &'a str,
+        invite: Option<&'a Uuid>,
+        registration: RegistrationMode,
+    ) -> ApiResult<User> {
+        let mut trx = self.db.begin().await?;
+
+        let (taken,): (i64,) =
+            sqlx::query_as(r#"SELECT COUNT(*) FROM account WHERE provider = ? AND id = ?"#)
+                .bind(PASSWORD_PROVIDER)
+                .bind(username)
+                .fetch_one(&mut *trx)
+                .await?;
+        if taken > 0 {
+            return Err(ApiError::BadRequest(
+                "That username is already taken".to_string(),
+            ));
         }
 
-        let id = Uuid::new_v4();
-        let now = OffsetDateTime::now_utc();
+        let user = Self::create_user(
+            &mut trx,
+            name,
+            PASSWORD_PROVIDER,
+            username,
+            Some(password_hash),
+            invite,
+            registration,
+        )
+        .await?;
+
+        trx.commit().await?;
+        Ok(user)
+    }
+
+    // Changing the email address means it needs verifying again
+    async fn update_profile<'a>(
+        &'a self,
+        id: &'a Uuid,
+        profile: &'a Profile<'a>,
+    ) -> ApiResult<User> {
         let user = sqlx::query_as::<_, User>(
             r#"
-            INSERT INTO user (
-                id,
-                name,
-                created_at
-            ) VALUES 
-                ($1, $2, $3) 
-            RETURNING 
+            UPDATE user SET
+                name = $1,
+                email_verified = email_verified AND email IS $2,
+                email = $2,
+                timezone = $3,
+                week_start = $4,
+                locale = $5,
+                updated_at = $6
+            WHERE
+                id = $7
+            RETURNING
                 id,
                 name,
                 created_at,
-                updated_at
+                updated_at,
+                disabled_at,
+                is_admin,
+                pending,
+                email,
+                email_verified,
+                timezone,
+                week_start,
+                locale
             "#,
         )
+        .bind(profile.name)
+        .bind(profile.email)
+        .bind(profile.timezone)
+        .bind(profile.week_start)
+        .bind(profile.locale)
+        .bind(OffsetDateTime::now_utc())
         .bind(id)
-        .bind(&response.name)
-        .bind(now)
-        .fetch_one(&mut *trx)
-        .await?;
-
-        sqlx::query(r#"INSERT INTO account (id, provider, user_id) VALUES ($1, $2, $3)"#)
-            .bind(&response.sub)
-            .bind("google")
-            .bind(&user.id)
-            .execute(&mut *trx)
-            .await?;
-
-        trx.commit().await?;
+        .fetch_optional(&self.db)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+        self.users.remove(id);
         Ok(user)
     }
 
+    // Purge the user and everything they own, along with all of their sessions
     async fn delete_user<'a>(&'a self, id: &'a Uuid) -> ApiResult<()> {
-        sqlx::query(r#"DELETE FROM user WHERE id = ?"#)
+        let mut trx = self.db.begin().await?;
+
+        // Unused login links only know the address they were sent to
+        sqlx::query(
+            r#"
+            DELETE FROM magic_link WHERE email IN (
+                SELECT id FROM account WHERE user_id = $1 AND provider = $2
+            )
+            "#,
+        )
+        .bind(id)
+        .bind(EMAIL_PROVIDER)
+        .execute(&mut *trx)
+        .await?;
+        for statement in USER_DATA_DELETES {
+            sqlx::query(statement).bind(id).execute(&mut *trx).await?;
+        }
+        let result = sqlx::query(r#"DELETE FROM user WHERE id = ?"#)
             .bind(id)
-            .execute(&self.db)
+            .execute(&mut *trx)
             .await?;
+        if result.rows_affected() == 0 {
+            return Err(ApiError::NotFound);
+        }
+        // Checked in the same transaction, so two admins deleting themselves at
+        // once can't leave everyone else without one
+        let (orphaned,): (bool,) = sqlx::query_as(
+            r#"
+            SELECT EXISTS (SELECT 1 FROM user)
+                AND NOT EXISTS (SELECT 1 FROM user WHERE is_admin = TRUE)
+            "#,
+        )
+        .fetch_one(&mut *trx)
+        .await?;
+        if orphaned {
+            return Err(ApiError::Forbidden(
+                "Make someone else an admin before deleting the last admin".to_string(),
+            ));
+        }
+
+        trx.commit().await?;
+        self.users.remove(id);
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{RegistrationMode, UserDataLayer};
+    use crate::{auth::UserResponse, database::test_database, error::ApiError};
+
+    fn login(sub: &str) -> UserResponse {
+        UserResponse {
+            sub: sub.to_string(),
+            email: None,
+            email_verified: false,
+            name: sub.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn the_first_user_becomes_admin() {
+        let db = test_database().await;
+
+        let first = db
+            .upsert_user("github", &login("first"), None, RegistrationMode::Open)
+            .await
+            .unwrap();
+        assert!(first.is_admin);
+        let second = db
+            .upsert_user("github", &login("second"), None, RegistrationMode::Open)
+            .await
+            .unwrap();
+        assert!(!second.is_admin);
+
+        // Logging in again doesn't create anyone new
+        let first = db
+            .upsert_user("github", &login("first"), None, RegistrationMode::Open)
+            .await
+            .unwrap();
+        assert!(first.is_admin);
+    }
+
+    #[tokio::test]
+    async fn the_first_user_needs_no_invite() {
+        let db = test_database().await;
+        assert!(!db.has_users().await.unwrap());
+
+        let first = db
+            .upsert_user("github", &login("first"), None, RegistrationMode::Invite)
+            .await
+            .unwrap();
+        assert!(first.is_admin);
+        assert!(db.has_users().await.unwrap());
+        let result = db
+            .upsert_user("github", &login("second"), None, RegistrationMode::Invite)
+            .await;
+        assert!(matches!(result, Err(ApiError::Forbidden(_))));
+    }
+
+    #[tokio::test]
+    async fn the_first_user_skips_approval_but_not_closed_sign_ups() {
+        let db = test_database().await;
+        let result = db
+            .upsert_user("github", &login("first"), None, RegistrationMode::Closed)
+            .await;
+        assert!(matches!(result, Err(ApiError::Forbidden(_))));
+
+        let first = db
+            .upsert_user("github", &login("first"), None, RegistrationMode::Approval)
+            .await
+            .unwrap();
+        assert!(!first.pending);
+        let second = db
+            .upsert_user("github", &login("second"), None, RegistrationMode::Approval)
+            .await
+            .unwrap();
+        assert!(second.pending);
+    }
+
+    #[tokio::test]
+    async fn the_last_admin_cant_be_deleted_while_others_remain() {
+        let db = test_database().await;
+        let admin = db
+            .upsert_user("github", &login("admin"), None, RegistrationMode::Open)
+            .await
+            .unwrap();
+        let other = db
+            .upsert_user("github", &login("other"), None, RegistrationMode::Open)
+            .await
+            .unwrap();
+
+        let result = db.delete_user(&admin.id).await;
+        assert!(matches!(result, Err(ApiError::Forbidden(_))));
+        assert!(db.get_user(&admin.id).await.unwrap().is_some());
+
+        db.delete_user(&other.id).await.unwrap();
+        assert!(matches!(
+            db.delete_user(&other.id).await,
+            Err(ApiError::NotFound)
+        ));
+        // Nobody is left to need an admin
+        db.delete_user(&admin.id).await.unwrap();
+    }
+}
@@ -0,0 +1,132 @@
+use axum::{extract::State, response::Html, Form};
+use maud::html;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    auth::Admin,
+    database::DataLayer,
+    error::{ApiError, ApiResult},
+    state::AppState,
+    templates::admin::{admin, invites_card, totals_card, users_card},
+};
+
+pub async fn admin_dashboard<T: for<'a> DataLayer<'a>>(
+    Admin(user): Admin,
+    State(state): State<AppState<T>>,
+) -> ApiResult<Html<String>> {
+    let totals = state.db.get_instance_totals().await?;
+    let users = state.db.get_user_summaries().await?;
+    let invites = state.db.get_all_invites().await?;
+    let markup = admin(html! {
+        (totals_card(&totals))
+        (users_card(&users, &user.id, None))
+        (invites_card(&invites, None))
+    });
+    Ok(Html(markup.into_string()))
+}
+
+// Admins can't disable, delete or demote themselves, so there's always one left
+fn check_not_self(admin_id: &Uuid, id: &Uuid) -> ApiResult<()> {
+    if admin_id == id {
+        return Err(ApiError::BadRequest(
+            "You can't change your own account from here".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+async fn render_users_card<T: for<'a> DataLayer<'a>>(
+    state: &AppState<T>,
+    admin_id: &Uuid,
+    notice: &str,
+) -> ApiResult<Html<String>> {
+    let users = state.db.get_user_summaries().await?;
+    Ok(Html(
+        users_card(&users, admin_id, Some(notice)).into_string(),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct SetDisabledRequest {
+    id: Uuid,
+    disabled: bool,
+}
+
+pub async fn set_user_disabled<T: for<'a> DataLayer<'a>>(
+    Admin(user): Admin,
+    State(state): State<AppState<T>>,
+    Form(body): Form<SetDisabledRequest>,
+) -> ApiResult<Html<String>> {
+    check_not_self(&user.id, &body.id)?;
+    state.db.set_user_disabled(&body.id, body.disabled).await?;
+    let notice = if body.disabled {
+        "User disabled"
+    } else {
+        "User enabled"
+    };
+    render_users_card(&state, &user.id, notice).await
+}
+
+#[derive(Deserialize)]
+pub struct SetAdminRequest {
+    id: Uuid,
+    admin: bool,
+}
+
+pub async fn set_user_admin<T: for<'a> DataLayer<'a>>(
+    Admin(user): Admin,
+    State(state): State<AppState<T>>,
+    Form(body): Form<SetAdminRequest>,
+) -> ApiResult<Html<String>> {
+    check_not_self(&user.id, &body.id)?;
+    state.db.set_user_admin(&body.id, body.admin).await?;
+    let notice = if body.admin {
+        "User is now an admin"
+    } else {
+        "User is no longer an admin"
+    };
+    render_users_card(&state, &user.id, notice).await
+}
+
+#[derive(Deserialize)]
+pub struct AdminUserRequest {
+    id: Uuid,
+}
+
+// Let in someone who signed up while registrations needed approving
+pub async fn approve_user<T: for<'a> DataLayer<'a>>(
+    Admin(user): Admin,
+    State(state): State<AppState<T>>,
+    Form(body): Form<AdminUserRequest>,
+) -> ApiResult<Html<String>> {
+    state.db.approve_user(&body.id).await?;
+    render_users_card(&state, &user.id, "User approved").await
+}
+
+pub async fn delete_user<T: for<'a> DataLayer<'a>>(
+    Admin(user): Admin,
+    State(state): State<AppState<T>>,
+    Form(body): Form<AdminUserRequest>,
+) -> ApiResult<Html<String>> {
+    check_not_self(&user.id, &body.id)?;
+    state.db.delete_user(&body.id).await?;
+    render_users_card(&state, &user.id, "User deleted").await
+}
+
+#[derive(Deserialize)]
+pub struct AdminInviteRequest {
+    id: Uuid,
+}
+
+pub async fn revoke_invite<T: for<'a> DataLayer<'a>>(
+    Admin(_): Admin,
+    State(state): State<AppState<T>>,
+    Form(body): Form<AdminInviteRequest>,
+) -> ApiResult<Html<String>> {
+    state.db.revoke_invite(&body.id, None).await?;
+    let invites = state.db.get_all_invites().await?;
+    Ok(Html(
+        invites_card(&invites, Some("Invite revoked")).into_string(),
+    ))
+}
@@ -3,7 +3,13 @@ use serde::Deserialize;
 use time::Date;
 use uuid::Uuid;
 
-use crate::{database::DataLayer, state::AppState, templates::components::routine_entry};
+use crate::{
+    database::DataLayer,
+    error::{ApiError, ApiResult},
+    models::users::User,
+    state::AppState,
+    templates::components::routine_entry,
+};
 
 #[derive(Deserialize)]
 pub struct ToggleEntryRequest {
@@ -12,23 +18,20 @@ pub struct ToggleEntryRequest {
 }
 
 pub async fn toggle_entry<T: for<'a> DataLayer<'a>>(
+    user: User,
     State(state): State<AppState<T>>,
     Form(body): Form<ToggleEntryRequest>,
-) -> Html<String> {
-    let complete = state
-        .db
-        .toggle_entries(&body.date, &body.routine_id)
-        .await
-        .unwrap();
-
+) -> ApiResult<Html<String>> {
     let routine = state
         .db
-        .get_routine(&body.routine_id)
-        .await
-        .unwrap()
-        .unwrap();
+        .get_routine(&body.routine_id, &user.id)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    let existed = state
+        .db
+        .toggle_entries(&body.date, &routine.id, &user.id)
+        .await?;
 
-    let markup = routine_entry(&body.date, !complete, &routine.color);
-    tracing::info!("{}", markup.clone().into_string());
-    Html(markup.into_string())
+    let markup = routine_entry(&body.date, !existed, &routine.color);
+    Ok(Html(markup.into_string()))
 }
@@ -1,11 +1,110 @@
-use axum::extract::State;
+use axum::{extract::State, response::Html, Form};
+use serde::Deserialize;
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
 
-use crate::{database::DataLayer, models::users::User, state::AppState};
+use crate::{
+    database::DataLayer,
+    error::{ApiError, ApiResult},
+    models::users::User,
+    state::AppState,
+    templates::invites::{invites_card, invites_page},
+};
 
+// Invites made from the navbar last a week and let one person sign up
+const DEFAULT_EXPIRY_DAYS: i64 = 7;
+const MAX_EXPIRY_DAYS: i64 = 30;
+
+fn invite_url<T: for<'a> DataLayer<'a>>(state: &AppState<T>, id: &Uuid) -> String {
+    format!("{}?invite={}", state.env.client_url, id)
+}
+
+async fn new_invite<T: for<'a> DataLayer<'a>>(
+    state: &AppState<T>,
+    user: &User,
+    expiry_days: i64,
+    max_uses: i64,
+) -> ApiResult<Uuid> {
+    if !(1..=MAX_EXPIRY_DAYS).contains(&expiry_days) {
+        return Err(ApiError::BadRequest("Invalid invite expiry".to_string()));
+    }
+    if !(1..=state.env.invite_max_uses).contains(&max_uses) {
+        return Err(ApiError::BadRequest(format!(
+            "Invites can be used by 1-{} people",
+            state.env.invite_max_uses
+        )));
+    }
+    let expires_at = OffsetDateTime::now_utc() + Duration::days(expiry_days);
+    let quota = (!user.is_admin).then_some(state.env.invite_quota);
+    state
+        .db
+        .create_invite(&user.id, Some(expires_at), max_uses, quota)
+        .await
+}
+
+// Copied straight to the clipboard by the navbar button
 pub async fn create_invite<T: for<'a> DataLayer<'a>>(
     user: User,
     State(state): State<AppState<T>>,
-) -> String {
-    let token = state.db.create_invite(&user.id).await.unwrap();
-    format!("{}?invite={}", state.env.client_url, token.to_string())
+) -> ApiResult<String> {
+    let id = new_invite(&state, &user, DEFAULT_EXPIRY_DAYS, 1).await?;
+    Ok(invite_url(&state, &id))
+}
+
+pub async fn sent_invites<T: for<'a> DataLayer<'a>>(
+    user: User,
+    State(state): State<AppState<T>>,
+) -> ApiResult<Html<String>> {
+    let invites = state.db.get_sent_invites(&user.id).await?;
+    let markup = invites_page(invites_card(
+        &invites,
+        &state.env.client_url,
+        state.env.invite_max_uses,
+        None,
+    ));
+    Ok(Html(markup.into_string()))
+}
+
+#[derive(Deserialize)]
+pub struct CreateInviteRequest {
+    expires: i64,
+    max_uses: i64,
+}
+
+pub async fn create_invite_from_page<T: for<'a> DataLayer<'a>>(
+    user: User,
+    State(state): State<AppState<T>>,
+    Form(body): Form<CreateInviteRequest>,
+) -> ApiResult<Html<String>> {
+    let id = new_invite(&state, &user, body.expires, body.max_uses).await?;
+    let invites = state.db.get_sent_invites(&user.id).await?;
+    let url = invite_url(&state, &id);
+    let markup = invites_card(
+        &invites,
+        &state.env.client_url,
+        state.env.invite_max_uses,
+        Some(&format!("New invite: {url}")),
+    );
+    Ok(Html(markup.into_string()))
+}
+
+#[derive(Deserialize)]
+pub struct RevokeInviteRequest {
+    id: Uuid,
+}
+
+pub async fn revoke_sent_invite<T: for<'a> DataLayer<'a>>(
+    user: User,
+    State(state): State<AppState<T>>,
+    Form(body): Form<RevokeInviteRequest>,
+) -> ApiResult<Html<String>> {
+    state.db.revoke_invite(&body.id, Some(&user.id)).await?;
+    let invites = state.db.get_sent_invites(&user.id).await?;
+    let markup = invites_card(
+        &invites,
+        &state.env.client_url,
+        state.env.invite_max_uses,
+        Some("Invite revoked"),
+    );
+    Ok(Html(markup.into_string()))
 }
@@ -1,9 +1,20 @@
+mod admin;
 mod entries;
 mod invite;
 mod root;
 mod routines;
+mod settings;
 
+pub use admin::{
+    admin_dashboard, approve_user, delete_user, revoke_invite, set_user_admin, set_user_disabled,
+};
 pub use entries::toggle_entry;
-pub use invite::create_invite;
+pub use invite::{create_invite, create_invite_from_page, revoke_sent_invite, sent_invites};
 pub use root::root;
-pub use routines::create_routine;
+pub use routines::{
+    archive_routine, archived_routines, create_routine, delete_routine, edit_routine_form,
+    get_routine_card, pause_routine, restore_routine, resume_routine, update_routine,
+};
+pub use settings::{
+    account_settings, delete_account, export_account, unlink_identity, update_profile,
+};
@@ -3,23 +3,33 @@ use axum::{
     response::Html,
 };
 use serde::Deserialize;
-use time::{ext::NumericalDuration, Date, Duration, OffsetDateTime};
+use std::collections::HashSet;
+use time::{ext::NumericalDuration, Date, Duration};
 
 use uuid::Uuid;
 
 use crate::{
+    auth::redirect::safe_return_to,
     database::DataLayer,
-    models::{entries::RoutineEntry, invites::InviteStatus, users::User},
+    error::ApiResult,
+    models::{entries::RoutineEntry, users::User},
     state::AppState,
-    templates::{home::index, login::LoginInvite},
+    templates::{
+        home::index,
+        login::{LoginInvite, LoginOptions},
+    },
+};
+use crate::{
+    models::routines::{Routine, RoutinePause, RoutineWithEntries},
+    templates::login::login,
 };
-use crate::{models::routines::RoutineWithEntries, templates::login::login};
 
 pub const NUM_ENTRIES: i64 = 60;
 
 #[derive(Deserialize)]
 pub struct QueryParams {
     invite: Option<String>,
+    return_to: Option<String>,
 }
 
 async fn parse_invite<T: for<'a> DataLayer<'a>>(
@@ -41,7 +51,7 @@ async fn parse_invite<T: for<'a> DataLayer<'a>>(
         .ok()
         .flatten()
         .map_or(LoginInvite::None, |invite| {
-            if invite.status == InviteStatus::Sent {
+            if invite.is_open() {
                 LoginInvite::Invite(invite.id.to_string())
             } else {
                 LoginInvite::InvalidInvite
@@ -53,37 +63,86 @@ pub async fn root<T: for<'a> DataLayer<'a>>(
     user: Option<User>,
     State(state): State<AppState<T>>,
     Query(query): Query<QueryParams>,
-) -> Html<String> {
+) -> ApiResult<Html<String>> {
     let Some(user) = user else {
+        let providers = state.providers.clone();
+        let options = LoginOptions {
+            providers: &providers,
+            password: state.env.password_login,
+            email: state.env.email_login,
+            registration: state.registration().await?,
+        };
+        let return_to = query.return_to.as_deref().and_then(safe_return_to);
         let invite = parse_invite(query.invite, state).await;
-        return Html(login(invite).into_string());
+        return Ok(Html(login(invite, &options, return_to).into_string()));
     };
-    let routines = state.db.get_routines(&user.id).await.unwrap();
-    let ids = routines.iter().map(|r| r.id).collect();
-    let all_entries = state.db.get_entries(&ids).await.unwrap();
+    let today = user.today();
+    let routines = state.db.get_routines(&user.id, Some(false)).await?;
+    let ids: Vec<_> = routines.iter().map(|r| r.id).collect();
+    let all_entries = state.db.get_entries(&user.id, &ids).await?;
+    let pauses = state.db.get_pauses(&user.id).await?;
     let data: Vec<_> = routines
         .into_iter()
-        .map(|r| {
-            let entries = build_entry_table(&r.id, &all_entries, NUM_ENTRIES);
-            RoutineWithEntries {
-                routine: r,
-                entries,
-            }
-        })
+        .map(|r| routine_with_entries(r, &all_entries, &pauses, NUM_ENTRIES, today))
         .collect();
     let markup = index(&data);
-    Html(markup.into_string())
+    Ok(Html(markup.into_string()))
+}
+
+pub fn routine_with_entries(
+    routine: Routine,
+    entries: &[RoutineEntry],
+    pauses: &[RoutinePause],
+    size: i64,
+    today: Date,
+) -> RoutineWithEntries {
+    RoutineWithEntries {
+        entries: build_entry_table(&routine.id, entries, size, today),
+        streak: current_streak(&routine, entries, pauses, today),
+        routine,
+    }
 }
 
-pub fn build_entry_table(routine: &Uuid, entries: &[RoutineEntry], size: i64) -> Vec<(Date, bool)> {
-    let now = OffsetDateTime::now_utc();
-    let start = Date::from_calendar_date(now.year(), now.month(), now.day())
-        .unwrap()
-        .checked_add(-Duration::days(size))
-        .unwrap();
+// Days done in a row, counting back from the user's today. Today doesn't break the streak
+// until it's over, and paused days are skipped over rather than breaking it
+pub fn current_streak(
+    routine: &Routine,
+    entries: &[RoutineEntry],
+    pauses: &[RoutinePause],
+    today: Date,
+) -> i64 {
+    let done: HashSet<Date> = entries
+        .iter()
+        .filter(|e| e.routine_id == routine.id)
+        .map(|e| e.date)
+        .collect();
+    let mut day = if done.contains(&today) {
+        Some(today)
+    } else {
+        today.previous_day()
+    };
+    let mut streak = 0;
+    while let Some(date) = day {
+        if !routine.is_paused_on(date, pauses) {
+            if !done.contains(&date) {
+                break;
+            }
+            streak += 1;
+        }
+        day = date.previous_day();
+    }
+    streak
+}
+
+pub fn build_entry_table(
+    routine: &Uuid,
+    entries: &[RoutineEntry],
+    size: i64,
+    today: Date,
+) -> Vec<(Date, bool)> {
+    let start = today.checked_add(-Duration::days(size)).unwrap();
 
     (0..size)
-        .into_iter()
         .map(|i| {
             let date = start.checked_add(i.days()).unwrap();
             (
@@ -1,11 +1,27 @@
-use axum::{extract::State, response::Html, Form};
+use axum::{
+    extract::{Path, State},
+    response::Html,
+    Form,
+};
 use serde::Deserialize;
+use time::OffsetDateTime;
+use uuid::Uuid;
 
 use crate::{
-    database::DataLayer, models::users::User, state::AppState, templates::components::routine_card,
+    database::DataLayer,
+    error::{ApiError, ApiResult},
+    models::{
+        routines::{Routine, RoutineWithEntries},
+        users::User,
+    },
+    state::AppState,
+    templates::{
+        components::{routine_card, routine_edit_card},
+        home::archived,
+    },
 };
 
-use super::root::{build_entry_table, NUM_ENTRIES};
+use super::root::{routine_with_entries, NUM_ENTRIES};
 
 #[derive(Deserialize)]
 pub struct CreateRoutineRequest {
@@ -23,7 +39,162 @@ pub async fn create_routine<T: for<'a> DataLayer<'a>>(
         .create_routine(&body.title, &body.color, &user.id)
         .await
         .unwrap();
-    let entries = build_entry_table(&routine.id, &vec![], NUM_ENTRIES);
-    let markup = routine_card(&routine, &entries);
+    let routine = routine_with_entries(routine, &[], &[], NUM_ENTRIES, user.today());
+    let markup = routine_card(&routine);
     Html(markup.into_string())
 }
+
+// Someone else's routine is a 404, so ids can't be probed for
+async fn owned_routine<T: for<'a> DataLayer<'a>>(
+    state: &AppState<T>,
+    user: &User,
+    id: &Uuid,
+) -> ApiResult<Routine> {
+    state
+        .db
+        .get_routine(id, &user.id)
+        .await?
+        .ok_or(ApiError::NotFound)
+}
+
+// Archived routines are shown with their whole history, rather than the last few weeks
+fn history_days(routine: &Routine) -> i64 {
+    if !routine.is_archived() {
+        return NUM_ENTRIES;
+    }
+    let age = OffsetDateTime::now_utc() - routine.created_at;
+    NUM_ENTRIES.max(age.whole_days() + 1)
+}
+
+async fn render_routine_card<T: for<'a> DataLayer<'a>>(
+    state: &AppState<T>,
+    user: &User,
+    routine: Routine,
+) -> ApiResult<Html<String>> {
+    let entries = state
+        .db
+        .get_entries(&routine.user_id, &[routine.id])
+        .await?;
+    let pauses = state.db.get_pauses(&routine.user_id).await?;
+    let size = history_days(&routine);
+    let routine = routine_with_entries(routine, &entries, &pauses, size, user.today());
+    Ok(Html(routine_card(&routine).into_string()))
+}
+
+// Swapped back in when editing is cancelled
+pub async fn get_routine_card<T: for<'a> DataLayer<'a>>(
+    user: User,
+    State(state): State<AppState<T>>,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Html<String>> {
+    let routine = owned_routine(&state, &user, &id).await?;
+    render_routine_card(&state, &user, routine).await
+}
+
+pub async fn edit_routine_form<T: for<'a> DataLayer<'a>>(
+    user: User,
+    State(state): State<AppState<T>>,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Html<String>> {
+    let routine = owned_routine(&state, &user, &id).await?;
+    Ok(Html(routine_edit_card(&routine).into_string()))
+}
+
+#[derive(Deserialize)]
+pub struct UpdateRoutineRequest {
+    title: String,
+    color: String,
+}
+
+// Colors come from `<input type="color">`, which always gives `#rrggbb`
+fn is_hex_color(color: &str) -> bool {
+    color.len() == 7 && color.starts_with('#') && color[1..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+pub async fn update_routine<T: for<'a> DataLayer<'a>>(
+    user: User,
+    State(state): State<AppState<T>>,
+    Path(id): Path<Uuid>,
+    Form(body): Form<UpdateRoutineRequest>,
+) -> ApiResult<Html<String>> {
+    let title = body.title.trim();
+    if title.is_empty() {
+        return Err(ApiError::BadRequest("Routines need a name".to_string()));
+    }
+    if !is_hex_color(&body.color) {
+        return Err(ApiError::BadRequest("Invalid color".to_string()));
+    }
+    // Scoped to the owner, so someone else's routine is a 404
+    let routine = state
+        .db
+        .update_routine(&id, &user.id, title, &body.color)
+        .await?;
+    render_routine_card(&state, &user, routine).await
+}
+
+// Responds with nothing, so HTMX swaps the card out of the page
+pub async fn delete_routine<T: for<'a> DataLayer<'a>>(
+    user: User,
+    State(state): State<AppState<T>>,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Html<String>> {
+    // Scoped to the owner, so someone else's routine is a 404
+    state.db.delete_routine(&id, &user.id).await?;
+    Ok(Html(String::new()))
+}
+
+pub async fn archived_routines<T: for<'a> DataLayer<'a>>(
+    user: User,
+    State(state): State<AppState<T>>,
+) -> ApiResult<Html<String>> {
+    let routines = state.db.get_routines(&user.id, Some(true)).await?;
+    let ids: Vec<_> = routines.iter().map(|r| r.id).collect();
+    let entries = state.db.get_entries(&user.id, &ids).await?;
+    let pauses = state.db.get_pauses(&user.id).await?;
+    let today = user.today();
+    let data: Vec<RoutineWithEntries> = routines
+        .into_iter()
+        .map(|r| {
+            let size = history_days(&r);
+            routine_with_entries(r, &entries, &pauses, size, today)
+        })
+        .collect();
+    Ok(Html(archived(&data).into_string()))
+}
+
+// Archiving and restoring move the routine to the other page, so the card is removed
+pub async fn archive_routine<T: for<'a> DataLayer<'a>>(
+    user: User,
+    State(state): State<AppState<T>>,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Html<String>> {
+    state.db.set_routine_archived(&id, &user.id, true).await?;
+    Ok(Html(String::new()))
+}
+
+pub async fn restore_routine<T: for<'a> DataLayer<'a>>(
+    user: User,
+    State(state): State<AppState<T>>,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Html<String>> {
+    state.db.set_routine_archived(&id, &user.id, false).await?;
+    Ok(Html(String::new()))
+}
+
+pub async fn pause_routine<T: for<'a> DataLayer<'a>>(
+    user: User,
+    State(state): State<AppState<T>>,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Html<String>> {
+    let routine = state.db.pause_routine(&id, &user.id, user.today()).await?;
+    render_routine_card(&state, &user, routine).await
+}
+
+pub async fn resume_routine<T: for<'a> DataLayer<'a>>(
+    user: User,
+    State(state): State<AppState<T>>,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Html<String>> {
+    let routine = state.db.resume_routine(&id, &user.id, user.today()).await?;
+    render_routine_card(&state, &user, routine).await
+}
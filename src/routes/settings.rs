@@ -0,0 +1,301 @@
+use axum::{
+    extract::State,
+    http::{
+        header::{CONTENT_DISPOSITION, SET_COOKIE},
+        HeaderMap, HeaderValue,
+    },
+    response::{Html, IntoResponse, Response},
+    Form, Json,
+};
+use axum_extra::{headers, TypedHeader};
+use maud::html;
+use serde::{Deserialize, Serialize};
+use time_tz::timezones;
+
+use crate::{
+    auth::{email::normalize_email, redirect::HX_REDIRECT, SessionUser},
+    database::DataLayer,
+    error::{ApiError, ApiResult},
+    models::{
+        accounts::PASSWORD_PROVIDER,
+        users::{Profile, WeekStart},
+    },
+    state::AppState,
+    templates::settings::{
+        admin_card, api_tokens_card, delete_account_card, identities_card, passkeys_card,
+        password_card, profile_card, sessions_card, settings, totp_card, TotpCard,
+    },
+};
+
+// What has to be typed to delete an account, so it can't be done by a stray click
+const DELETE_CONFIRMATION: &str = "delete my account";
+const MAX_NAME_LENGTH: usize = 64;
+
+pub async fn account_settings<T: for<'a> DataLayer<'a>>(
+    SessionUser(user): SessionUser,
+    State(state): State<AppState<T>>,
+    cookies: Option<TypedHeader<headers::Cookie>>,
+) -> ApiResult<Html<String>> {
+    let accounts = state.db.get_accounts(&user.id).await?;
+    let username = accounts
+        .iter()
+        .find(|a| a.provider == PASSWORD_PROVIDER)
+        .map(|a| a.id.as_str());
+    let passkeys = state.db.get_passkeys(&user.id).await?;
+    let tokens = state.db.get_api_tokens(&user.id).await?;
+    let sessions = state.db.get_user_sessions(&user.id).await?;
+    let current_session =
+        cookies.and_then(|TypedHeader(cookies)| state.session_store.session_id(&cookies));
+    // An unfinished enrolment is started over from scratch
+    let totp = match state.db.get_totp(&user.id).await? {
+        Some(totp) if totp.is_enabled() => TotpCard::On {
+            recovery_codes_left: state.db.count_recovery_codes(&user.id).await?,
+        },
+        _ => TotpCard::Off,
+    };
+    let markup = settings(html! {
+        (profile_card(&user, None))
+        (identities_card(&accounts, &state.providers))
+        @if state.env.password_login {
+            (password_card(username, None))
+        }
+        (totp_card(totp, None))
+        (passkeys_card(&passkeys, None))
+        (api_tokens_card(&tokens, None))
+        (sessions_card(&sessions, current_session.as_deref(), None))
+        @if user.is_admin {
+            (admin_card())
+        }
+        (delete_account_card(DELETE_CONFIRMATION, None))
+    });
+    Ok(Html(markup.into_string()))
+}
+
+// A BCP 47 tag like `en`, `en-GB` or `zh-Hant-TW`
+fn is_language_tag(locale: &str) -> bool {
+    let mut parts = locale.split('-');
+    let language = parts.next().unwrap_or_default();
+    (2..=3).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_alphabetic())
+        && parts.all(|part| {
+            (1..=8).contains(&part.len()) && part.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+}
+
+#[derive(Deserialize)]
+pub struct ProfileRequest {
+    name: String,
+    email: String,
+    timezone: String,
+    week_start: WeekStart,
+    locale: String,
+}
+
+pub async fn update_profile<T: for<'a> DataLayer<'a>>(
+    SessionUser(user): SessionUser,
+    State(state): State<AppState<T>>,
+    Form(body): Form<ProfileRequest>,
+) -> ApiResult<Html<String>> {
+    let name = body.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(ApiError::BadRequest(format!(
+            "Names need to be 1-{MAX_NAME_LENGTH} characters"
+        )));
+    }
+    let email = match body.email.trim() {
+        "" => None,
+        email => Some(normalize_email(email)?),
+    };
+    let timezone = body.timezone.trim();
+    // Streaks are counted in it, so it has to be one we know
+    if timezones::get_by_name(timezone).is_none() {
+        return Err(ApiError::BadRequest(
+            "That doesn't look like a timezone".to_string(),
+        ));
+    }
+    let locale = body.locale.trim();
+    if !is_language_tag(locale) {
+        return Err(ApiError::BadRequest(
+            "That doesn't look like a language, try something like en-GB".to_string(),
+        ));
+    }
+
+    let profile = Profile {
+        name,
+        email: email.as_deref(),
+        timezone,
+        week_start: body.week_start,
+        locale,
+    };
+    let user = state.db.update_profile(&user.id, &profile).await?;
+    Ok(Html(
+        profile_card(&user, Some("Profile saved")).into_string(),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct UnlinkIdentityRequest {
+    provider: String,
+    id: String,
+}
+
+pub async fn unlink_identity<T: for<'a> DataLayer<'a>>(
+    SessionUser(user): SessionUser,
+    State(state): State<AppState<T>>,
+    Form(body): Form<UnlinkIdentityRequest>,
+) -> ApiResult<Html<String>> {
+    state
+        .db
+        .unlink_account(&user.id, &body.provider, &body.id)
+        .await?;
+    let accounts = state.db.get_accounts(&user.id).await?;
+    let markup = identities_card(&accounts, &state.providers);
+    Ok(Html(markup.into_string()))
+}
+
+// Everything the user has put in, for them to keep before deleting their account
+#[derive(Serialize)]
+struct AccountExport {
+    name: String,
+    email: Option<String>,
+    timezone: String,
+    week_start: WeekStart,
+    locale: String,
+    created_at: String,
+    logins: Vec<LoginExport>,
+    routines: Vec<RoutineExport>,
+    invites: Vec<InviteExport>,
+}
+
+#[derive(Serialize)]
+struct LoginExport {
+    provider: String,
+    id: String,
+}
+
+#[derive(Serialize)]
+struct RoutineExport {
+    title: String,
+    color: String,
+    created_at: String,
+    archived_at: Option<String>,
+    // Set while the routine is paused, with `pauses` holding the ones that have ended
+    paused_since: Option<String>,
+    pauses: Vec<PauseExport>,
+    // Every day it was done, oldest first
+    entries: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct PauseExport {
+    started_on: String,
+    ended_on: String,
+}
+
+#[derive(Serialize)]
+struct InviteExport {
+    created_at: String,
+    uses: i64,
+    max_uses: i64,
+    accepted_by: Vec<String>,
+}
+
+pub async fn export_account<T: for<'a> DataLayer<'a>>(
+    SessionUser(user): SessionUser,
+    State(state): State<AppState<T>>,
+) -> ApiResult<impl IntoResponse> {
+    let accounts = state.db.get_accounts(&user.id).await?;
+    let routines = state.db.get_routines(&user.id, None).await?;
+    let ids: Vec<_> = routines.iter().map(|r| r.id).collect();
+    let entries = state.db.get_entries(&user.id, &ids).await?;
+    let pauses = state.db.get_pauses(&user.id).await?;
+    let invites = state.db.get_sent_invites(&user.id).await?;
+
+    let export = AccountExport {
+        name: user.name,
+        email: user.email,
+        timezone: user.timezone,
+        week_start: user.week_start,
+        locale: user.locale,
+        created_at: user.created_at.to_string(),
+        logins: accounts
+            .into_iter()
+            .map(|account| LoginExport {
+                provider: account.provider,
+                id: account.id,
+            })
+            .collect(),
+        routines: routines
+            .into_iter()
+            .map(|routine| {
+                let mut dates: Vec<_> = entries
+                    .iter()
+                    .filter(|entry| entry.routine_id == routine.id)
+                    .map(|entry| entry.date)
+                    .collect();
+                dates.sort();
+                let mut routine_pauses: Vec<_> = pauses
+                    .iter()
+                    .filter(|pause| pause.routine_id == routine.id)
+                    .collect();
+                routine_pauses.sort_by_key(|pause| pause.started_on);
+                RoutineExport {
+                    title: routine.title,
+                    color: routine.color,
+                    created_at: routine.created_at.to_string(),
+                    archived_at: routine.archived_at.map(|at| at.to_string()),
+                    paused_since: routine.paused_since.map(|on| on.to_string()),
+                    pauses: routine_pauses
+                        .into_iter()
+                        .map(|pause| PauseExport {
+                            started_on: pause.started_on.to_string(),
+                            ended_on: pause.ended_on.to_string(),
+                        })
+                        .collect(),
+                    entries: dates.iter().map(ToString::to_string).collect(),
+                }
+            })
+            .collect(),
+        invites: invites
+            .into_iter()
+            .map(|sent| InviteExport {
+                created_at: sent.invite.created_at.to_string(),
+                uses: sent.invite.uses,
+                max_uses: sent.invite.max_uses,
+                accepted_by: sent.accepted_by,
+            })
+            .collect(),
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        CONTENT_DISPOSITION,
+        HeaderValue::from_static("attachment; filename=\"routines-export.json\""),
+    );
+    Ok((headers, Json(export)))
+}
+
+#[derive(Deserialize)]
+pub struct DeleteAccountRequest {
+    confirm: String,
+}
+
+pub async fn delete_account<T: for<'a> DataLayer<'a>>(
+    SessionUser(user): SessionUser,
+    State(state): State<AppState<T>>,
+    Form(body): Form<DeleteAccountRequest>,
+) -> ApiResult<Response> {
+    if body.confirm.trim().to_lowercase() != DELETE_CONFIRMATION {
+        let notice = format!("Type \"{DELETE_CONFIRMATION}\" to confirm");
+        let markup = delete_account_card(DELETE_CONFIRMATION, Some(&notice));
+        return Ok(Html(markup.into_string()).into_response());
+    }
+
+    // Takes every session with it, so this browser is logged out too
+    state.db.delete_user(&user.id).await?;
+
+    let mut headers = HeaderMap::new();
+    headers.insert(SET_COOKIE, state.session_store.cookies.clear_session()?);
+    headers.insert(HX_REDIRECT.clone(), HeaderValue::from_static("/"));
+    Ok(headers.into_response())
+}
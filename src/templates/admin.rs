@@ -0,0 +1,147 @@
+use maud::{html, Markup};
+use uuid::Uuid;
+
+use crate::{
+    models::{
+        admin::{InstanceTotals, InviteSummary, UserSummary},
+        invites::InviteStatus,
+    },
+    templates::components::{header, navbar},
+};
+
+pub fn admin(cards: Markup) -> Markup {
+    html! {
+        (header("Admin"))
+        body {
+            (navbar(true))
+            article .page-container {
+                div .settings-list {
+                    (cards)
+                }
+            }
+        }
+    }
+}
+
+pub fn totals_card(totals: &InstanceTotals) -> Markup {
+    html! {
+        div .card #admin-totals {
+            span .card-title {
+                "Instance"
+            }
+            ul .settings-rows {
+                li .settings-row { span { "Users" } span { (totals.users) } }
+                li .settings-row { span { "Disabled users" } span { (totals.disabled_users) } }
+                li .settings-row { span { "Awaiting approval" } span { (totals.pending_users) } }
+                li .settings-row { span { "Routines" } span { (totals.routines) } }
+                li .settings-row { span { "Entries" } span { (totals.entries) } }
+                li .settings-row { span { "Open invites" } span { (totals.open_invites) } }
+                li .settings-row { span { "Active sessions" } span { (totals.active_sessions) } }
+            }
+        }
+    }
+}
+
+// `admin_id` is the admin viewing the page, who can't lock themselves out
+pub fn users_card(users: &[UserSummary], admin_id: &Uuid, notice: Option<&str>) -> Markup {
+    html! {
+        div .card #admin-users {
+            span .card-title {
+                "Users"
+            }
+            @if let Some(notice) = notice {
+                p .callout { (notice) }
+            }
+            ul .settings-rows {
+                @for user in users {
+                    li .settings-row {
+                        span {
+                            (user.name)
+                            span .callout {
+                                @if user.is_admin { " admin," }
+                                @if user.pending { " awaiting approval," }
+                                @if let Some(disabled_at) = user.disabled_at {
+                                    " disabled " (disabled_at.date()) ","
+                                }
+                                " joined " (user.created_at.date())
+                                ", " (user.routine_count) " routines"
+                                ", " (user.entry_count) " entries"
+                            }
+                        }
+                        @if user.id != *admin_id {
+                            div .settings-actions {
+                                @if user.pending {
+                                    form hx-post="/admin/users/approve" hx-target="#admin-users" hx-swap="outerHTML" {
+                                        input type="hidden" name="id" value=(user.id) {}
+                                        button .secondary-button type="submit" { "Approve" }
+                                    }
+                                }
+                                form hx-post="/admin/users/admin" hx-target="#admin-users" hx-swap="outerHTML" {
+                                    input type="hidden" name="id" value=(user.id) {}
+                                    input type="hidden" name="admin" value=(if user.is_admin { "false" } else { "true" }) {}
+                                    button .secondary-button type="submit" {
+                                        @if user.is_admin { "Remove admin" } @else { "Make admin" }
+                                    }
+                                }
+                                form hx-post="/admin/users/disable" hx-target="#admin-users" hx-swap="outerHTML" {
+                                    input type="hidden" name="id" value=(user.id) {}
+                                    input type="hidden" name="disabled" value=(if user.disabled_at.is_some() { "false" } else { "true" }) {}
+                                    button .secondary-button type="submit" {
+                                        @if user.disabled_at.is_some() { "Enable" } @else { "Disable" }
+                                    }
+                                }
+                                form hx-post="/admin/users/delete" hx-target="#admin-users" hx-swap="outerHTML"
+                                    hx-confirm={"Delete " (user.name) " and all of their routines? This can't be undone"} {
+                                    input type="hidden" name="id" value=(user.id) {}
+                                    button .secondary-button .danger-button type="submit" { "Delete" }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn invites_card(invites: &[InviteSummary], notice: Option<&str>) -> Markup {
+    html! {
+        div .card #admin-invites {
+            span .card-title {
+                "Invites"
+            }
+            @if let Some(notice) = notice {
+                p .callout { (notice) }
+            }
+            ul .settings-rows {
+                @for invite in invites {
+                    li .settings-row {
+                        span {
+                            "From " (invite.sender_name)
+                            span .callout {
+                                ", sent " (invite.created_at.date())
+                                ", " (invite.uses) " of " (invite.max_uses) " uses"
+                                @if let Some(name) = &invite.accepted_by_name {
+                                    ", last by " (name)
+                                }
+                                @match invite.status {
+                                    InviteStatus::Revoked => { ", revoked" }
+                                    InviteStatus::Accepted => { ", used up" }
+                                    InviteStatus::Sent if invite.is_expired() => { ", expired" }
+                                    InviteStatus::Sent => {}
+                                }
+                            }
+                        }
+                        @if invite.status == InviteStatus::Sent && !invite.is_expired() {
+                            form hx-post="/admin/invites/revoke" hx-target="#admin-invites" hx-swap="outerHTML"
+                                hx-confirm="Revoke this invite?" {
+                                input type="hidden" name="id" value=(invite.id) {}
+                                button .danger-button type="submit" { "Revoke" }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
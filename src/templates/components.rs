@@ -1,4 +1,4 @@
-use crate::models::routines::Routine;
+use crate::models::routines::{Routine, RoutineWithEntries};
 use maud::{html, Markup, PreEscaped, DOCTYPE};
 use time::Date;
 
@@ -15,10 +15,13 @@ pub fn navbar(signed_in: bool) -> Markup {
                             // Evil
                             (PreEscaped(r#"window.copyUrl = function copyUrl() {
                                 fetch("/invite", {method: "POST"})
-                                    .then(res => res.text())
+                                    .then(res => res.ok ? res.text() : Promise.reject())
                                     .then(url => {
                                         navigator.clipboard.writeText(url);
                                         document.getElementById("invite").textContent = "Copied to clipboard!";
+                                    })
+                                    .catch(() => { document.getElementById("invite").textContent = "Couldn't create an invite"; })
+                                    .finally(() => {
                                         setTimeout(() => { document.getElementById("invite").textContent = "Invite"}, 2500);
                                     })
                             };
@@ -27,6 +30,12 @@ pub fn navbar(signed_in: bool) -> Markup {
                         button onclick="copyUrl()" id="invite" .invite {
                             "Invite"
                         }
+                        a .nav-link href="/invites" {
+                            "Invites"
+                        }
+                        a .nav-link href="/settings" {
+                            "Settings"
+                        }
                         a href="/logout" {
                             "Logout"
                         }
@@ -56,11 +65,61 @@ pub fn create_routine_form() -> Markup {
     }
 }
 
-pub fn routine_card(routine: &Routine, entries: &[(Date, bool)]) -> Markup {
+pub fn routine_card(data: &RoutineWithEntries) -> Markup {
+    let RoutineWithEntries {
+        routine,
+        entries,
+        streak,
+    } = data;
+    let target = format!("#routine-{}", routine.id);
     html! {
-        div .card {
-            span .card-title {
-                (routine.title)
+        div .card id={"routine-" (routine.id)} {
+            div .routine-header {
+                span {
+                    span .card-title {
+                        (routine.title)
+                    }
+                    span .callout {
+                        @if let Some(paused_since) = routine.paused_since {
+                            " paused since " (paused_since)
+                        } @else if *streak > 0 {
+                            " " (streak) " day streak"
+                        }
+                    }
+                }
+                div .routine-actions {
+                    @if routine.is_archived() {
+                        button .secondary-button type="button" hx-post={"/routine/" (routine.id) "/restore"}
+                            hx-target=(target) hx-swap="outerHTML" {
+                            "Restore"
+                        }
+                    } @else {
+                        button .secondary-button type="button" hx-get={"/routine/" (routine.id) "/edit"}
+                            hx-target=(target) hx-swap="outerHTML" {
+                            "Edit"
+                        }
+                        @if routine.paused_since.is_some() {
+                            button .secondary-button type="button" hx-post={"/routine/" (routine.id) "/resume"}
+                                hx-target=(target) hx-swap="outerHTML" {
+                                "Resume"
+                            }
+                        } @else {
+                            button .secondary-button type="button" hx-post={"/routine/" (routine.id) "/pause"}
+                                hx-target=(target) hx-swap="outerHTML" {
+                                "Pause"
+                            }
+                        }
+                        button .secondary-button type="button" hx-post={"/routine/" (routine.id) "/archive"}
+                            hx-target=(target) hx-swap="outerHTML" {
+                            "Archive"
+                        }
+                    }
+                    button .secondary-button .danger-button type="button" hx-delete={"/routine/" (routine.id)}
+                        hx-target=(target) hx-swap="outerHTML"
+                        hx-confirm={"Delete " (routine.title) " and all of its entries? This can't be undone"} {
+                        "Delete"
+                    }
+                }
             }
             input name="routine_id" value=(routine.id) type="hidden" {}
             div .entry-container {
@@ -72,6 +131,32 @@ pub fn routine_card(routine: &Routine, entries: &[(Date, bool)]) -> Markup {
     }
 }
 
+// Takes the place of the routine's card while it's being edited
+pub fn routine_edit_card(routine: &Routine) -> Markup {
+    html! {
+        form .card id={"routine-" (routine.id)} hx-post={"/routine/" (routine.id)} hx-swap="outerHTML" {
+            span .card-title {
+                "Edit routine"
+            }
+            div .form-body {
+                .form-row {
+                    input .title-input type="text" placeholder="Routine name" name="title" value=(routine.title) required;
+                    input .color-input type="color" name="color" value=(routine.color);
+                }
+                .form-row {
+                    button .create-button type="submit" {
+                        "Save"
+                    }
+                    button .secondary-button type="button" hx-get={"/routine/" (routine.id)}
+                        hx-target={"#routine-" (routine.id)} hx-swap="outerHTML" {
+                        "Cancel"
+                    }
+                }
+            }
+        }
+    }
+}
+
 pub fn routine_entry(date: &Date, complete: bool, color: &str) -> Markup {
     let bg_color = if complete { color } else { "#52525b" };
     html! {
@@ -91,7 +176,7 @@ pub fn header(page_title: &str) -> Markup {
         link rel="preconnect" href="https://fonts.googleapis.com" {}
         link rel="preconnect" href="https://fonts.gstatic.com" crossorigin {}
         link href="https://fonts.googleapis.com/css2?family=Fira+Mono:wght@400;500;700&display=swap" rel="stylesheet" {}
-        script src="static/js/htmx@1.9.5.js" {}
+        script src="/static/js/htmx@1.9.5.js" {}
         title { (page_title) }
     }
 }
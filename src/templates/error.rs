@@ -0,0 +1,26 @@
+use maud::{html, Markup};
+
+use crate::templates::components::{header, navbar};
+
+pub fn error_page(title: &str, message: &str) -> Markup {
+    html! {
+        (header("Routines"))
+        body {
+            (navbar(false))
+            article .page-container {
+                div .login-container {
+                    h2 .card-title {
+                        (title)
+                    }
+                    p .callout {
+                        (message)
+                    }
+                    hr { }
+                    a .login-button href="/" {
+                        "Back to Routines"
+                    }
+                }
+            }
+        }
+    }
+}
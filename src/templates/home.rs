@@ -10,10 +10,36 @@ pub fn index(routines: &[RoutineWithEntries]) -> Markup {
             article .page-container {
                 div .routine-card-list #routine-list {
                     @for routine in routines {
-                        (routine_card(&routine.routine, &routine.entries))
+                        (routine_card(routine))
                     }
                 }
                 (create_routine_form())
+                div .settings-actions {
+                    a .secondary-button href="/archived" { "Archived routines" }
+                }
+            }
+        }
+    }
+}
+
+// Routines that have been put away, with all of their history
+pub fn archived(routines: &[RoutineWithEntries]) -> Markup {
+    html! {
+        (header("Archived routines"))
+        body {
+            (navbar(true))
+            article .page-container {
+                div .routine-card-list {
+                    @for routine in routines {
+                        (routine_card(routine))
+                    }
+                    @if routines.is_empty() {
+                        p .callout { "Archived routines will show up here" }
+                    }
+                }
+                div .settings-actions {
+                    a .secondary-button href="/" { "Back to routines" }
+                }
             }
         }
     }
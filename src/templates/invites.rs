@@ -0,0 +1,83 @@
+use maud::{html, Markup};
+
+use crate::{
+    models::invites::{InviteStatus, SentInvite},
+    templates::components::{header, navbar},
+};
+
+pub fn invites_page(card: Markup) -> Markup {
+    html! {
+        (header("Invites"))
+        body {
+            (navbar(true))
+            article .page-container {
+                div .settings-list {
+                    (card)
+                }
+            }
+        }
+    }
+}
+
+pub fn invites_card(
+    invites: &[SentInvite],
+    client_url: &str,
+    max_uses: i64,
+    notice: Option<&str>,
+) -> Markup {
+    html! {
+        div .card #invites {
+            span .card-title {
+                "Invites"
+            }
+            @if let Some(notice) = notice {
+                p .callout { (notice) }
+            }
+            ul .settings-rows {
+                @for SentInvite { invite, accepted_by } in invites {
+                    li .settings-row {
+                        span {
+                            @match invite.status {
+                                InviteStatus::Revoked => { "Revoked" }
+                                InviteStatus::Accepted => { "Used up" }
+                                InviteStatus::Sent if invite.is_expired() => { "Expired" }
+                                InviteStatus::Sent => { "Open" }
+                            }
+                            span .callout {
+                                ", sent " (invite.created_at.date())
+                                ", " (invite.uses) " of " (invite.max_uses) " uses"
+                                @if let (true, Some(expires_at)) = (invite.is_open(), invite.expires_at) {
+                                    ", expires " (expires_at.date())
+                                }
+                            }
+                            @if !accepted_by.is_empty() {
+                                p .callout { "Accepted by " (accepted_by.join(", ")) }
+                            }
+                            @if invite.is_open() {
+                                input .title-input type="text" value={(client_url) "?invite=" (invite.id)} readonly onclick="this.select()";
+                            }
+                        }
+                        @if invite.is_open() {
+                            form hx-post="/invites/revoke" hx-target="#invites" hx-swap="outerHTML"
+                                hx-confirm="Revoke this invite? Nobody else will be able to sign up with it" {
+                                input type="hidden" name="id" value=(invite.id) {}
+                                button .danger-button type="submit" { "Revoke" }
+                            }
+                        }
+                    }
+                }
+            }
+            form .form-body hx-post="/invites" hx-target="#invites" hx-swap="outerHTML" {
+                div .form-row {
+                    select .title-input name="expires" {
+                        option value="1" { "Expires in 1 day" }
+                        option value="7" selected { "Expires in 7 days" }
+                        option value="30" { "Expires in 30 days" }
+                    }
+                    input .title-input type="number" name="max_uses" value="1" min="1" max=(max_uses) required;
+                }
+                button .create-button type="submit" { "Create invite" }
+            }
+        }
+    }
+}
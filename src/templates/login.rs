@@ -1,6 +1,11 @@
 use maud::{html, Markup};
+use uuid::Uuid;
 
-use crate::templates::components::{header, navbar};
+use crate::{
+    auth::{providers::Providers, redirect::return_to_query},
+    models::users::RegistrationMode,
+    templates::components::{header, navbar},
+};
 
 pub enum LoginInvite {
     Invite(String),
@@ -8,7 +13,103 @@ pub enum LoginInvite {
     None,
 }
 
-pub fn login(invite: LoginInvite) -> Markup {
+// The ways this instance lets people log in
+pub struct LoginOptions<'a> {
+    pub providers: &'a Providers,
+    pub password: bool,
+    pub email: bool,
+    pub registration: RegistrationMode,
+}
+
+// `return_to` is where to go after logging in, already checked to be on this site
+fn login_buttons(options: &LoginOptions, invite: Option<&str>, return_to: Option<&str>) -> Markup {
+    let (verb, query) = match (invite, return_to) {
+        (Some(invite), _) => ("Sign up", format!("?invite={invite}")),
+        (None, Some(return_to)) => ("Login", format!("?{}", return_to_query(return_to))),
+        (None, None) => ("Login", String::new()),
+    };
+    html! {
+        div .login-buttons {
+            @for provider in options.providers.iter() {
+                a .login-button href={"/auth/" (provider.name) (query)} {
+                    (verb) " with " (provider.label)
+                }
+            }
+            // Passkeys are added from settings, so they can only be used to log in
+            @if invite.is_none() {
+                script src="/static/js/passkey.js" {}
+                button .login-button type="button" onclick="loginWithPasskey()" {
+                    "Login with a passkey"
+                }
+            }
+            @if options.password {
+                @match invite {
+                    Some(invite) => {
+                        a .login-button href={"/register?invite=" (invite)} {
+                            "Sign up with a username"
+                        }
+                    }
+                    None => {
+                        form .login-form method="post" action="/login" {
+                            @if let Some(return_to) = return_to {
+                                input type="hidden" name="return_to" value=(return_to);
+                            }
+                            input .title-input type="text" name="username" placeholder="Username" autocomplete="username" required;
+                            input .title-input type="password" name="password" placeholder="Password" autocomplete="current-password" required;
+                            button .login-button type="submit" { "Login" }
+                        }
+                        @if options.registration.allows_uninvited() {
+                            a .login-button href="/register" { "Sign up with a username" }
+                        }
+                    }
+                }
+            }
+            @if options.email {
+                form .login-form method="post" action="/login/email" {
+                    @if let Some(invite) = invite {
+                        input type="hidden" name="invite" value=(invite);
+                    }
+                    input .title-input type="email" name="email" placeholder="Email address" autocomplete="email" required;
+                    button .login-button type="submit" { (verb) " with an email link" }
+                }
+            }
+        }
+    }
+}
+
+// Tells people arriving without a usable invite whether they can sign up
+fn sign_up_notice(registration: RegistrationMode) -> Markup {
+    html! {
+        @match registration {
+            RegistrationMode::Open => {
+                p .callout {
+                    "New here? Logging in for the first time creates your account"
+                }
+            }
+            RegistrationMode::Invite => {
+                h2 .card-title {
+                    "Not currently accepting new sign ups"
+                }
+                p .callout {
+                    "An invite link is required to sign up"
+                }
+            }
+            RegistrationMode::Closed => {
+                h2 .card-title {
+                    "Not currently accepting new sign ups"
+                }
+            }
+            RegistrationMode::Approval => {
+                p .callout {
+                    "New here? Logging in for the first time creates your account, "
+                    "which an admin will need to approve before you can use it"
+                }
+            }
+        }
+    }
+}
+
+pub fn login(invite: LoginInvite, options: &LoginOptions, return_to: Option<&str>) -> Markup {
     html! {
         (header("Routines"))
         body {
@@ -16,31 +117,21 @@ pub fn login(invite: LoginInvite) -> Markup {
             article .page-container {
                 div .login-container {
                     @match invite {
-                        LoginInvite::Invite(invite) => {
-                            a .login-button href={"auth/google?invite=" (invite)} {
-                                "Sign up with Google"
-                            }
+                        // Even an invite can't get someone in while sign ups are closed
+                        LoginInvite::Invite(invite) if options.registration != RegistrationMode::Closed => {
+                            (login_buttons(options, Some(&invite), None))
                         },
                         LoginInvite::InvalidInvite => {
                             h2 .card-title {
                                 "Invalid Invite"
                             }
                             hr { }
-                            a .login-button href="auth/google" {
-                                "Login with Google"
-                            }
+                            (login_buttons(options, None, return_to))
                         }
-                        LoginInvite::None => {
-                            h2 .card-title {
-                                "Not currently accepting new sign ups"
-                            }
-                            p .callout {
-                                "An invite link is required to sign up"
-                            }
+                        _ => {
+                            (sign_up_notice(options.registration))
                             hr { }
-                            a .login-button href="auth/google" {
-                                "Login with Google"
-                            }
+                            (login_buttons(options, None, return_to))
                         }
                     }
                 }
@@ -48,3 +139,89 @@ pub fn login(invite: LoginInvite) -> Markup {
         }
     }
 }
+
+pub fn register_page(invite: Option<&Uuid>) -> Markup {
+    html! {
+        (header("Sign up"))
+        body {
+            (navbar(false))
+            article .page-container {
+                div .login-container {
+                    h2 .card-title {
+                        "Sign up"
+                    }
+                    form .login-form method="post" action="/register" {
+                        @if let Some(invite) = invite {
+                            input type="hidden" name="invite" value=(invite);
+                        }
+                        input .title-input type="text" name="username" placeholder="Username" autocomplete="username" required;
+                        input .title-input type="text" name="name" placeholder="Display name" autocomplete="name";
+                        input .title-input type="password" name="password" placeholder="Password" autocomplete="new-password" required;
+                        input .title-input type="password" name="confirm_password" placeholder="Confirm password" autocomplete="new-password" required;
+                        button .login-button type="submit" { "Sign up" }
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn email_sent_page(email: &str) -> Markup {
+    html! {
+        (header("Check your email"))
+        body {
+            (navbar(false))
+            article .page-container {
+                div .login-container {
+                    h2 .card-title {
+                        "Check your email"
+                    }
+                    p .callout {
+                        "If " (email) " can log in here, we've sent it a login link. It expires in 15 minutes."
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn confirm_email_login_page(token: &str) -> Markup {
+    html! {
+        (header("Login"))
+        body {
+            (navbar(false))
+            article .page-container {
+                div .login-container {
+                    form .login-form method="post" action="/login/email/verify" {
+                        input type="hidden" name="token" value=(token);
+                        button .login-button type="submit" { "Continue to Routines" }
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn totp_page(notice: Option<&str>) -> Markup {
+    html! {
+        (header("Two-factor authentication"))
+        body {
+            (navbar(false))
+            article .page-container {
+                div .login-container {
+                    h2 .card-title {
+                        "Two-factor authentication"
+                    }
+                    p .callout {
+                        (notice.unwrap_or("Enter the code from your authenticator app, or a recovery code"))
+                    }
+                    form .login-form method="post" action="/login/totp" {
+                        input .title-input type="text" name="code" placeholder="Code" autocomplete="one-time-code" autofocus required;
+                        button .login-button type="submit" { "Continue" }
+                    }
+                    a .callout href="/logout" { "Cancel" }
+                }
+            }
+        }
+    }
+}
@@ -1,3 +1,7 @@
+pub mod admin;
 pub mod components;
+pub mod error;
 pub mod home;
+pub mod invites;
 pub mod login;
+pub mod settings;
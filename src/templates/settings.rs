@@ -0,0 +1,426 @@
+use maud::{html, Markup, PreEscaped};
+use qrcode::{Color, QrCode};
+
+use crate::{
+    auth::providers::Providers,
+    models::{
+        accounts::{Account, EMAIL_PROVIDER, PASSWORD_PROVIDER},
+        api_tokens::{ApiToken, TokenScope},
+        passkeys::Passkey,
+        sessions::ActiveSession,
+        users::{User, WeekStart},
+    },
+    templates::components::{header, navbar},
+};
+
+pub fn settings(cards: Markup) -> Markup {
+    html! {
+        (header("Settings"))
+        body {
+            (navbar(true))
+            article .page-container {
+                div .settings-list {
+                    (cards)
+                }
+            }
+        }
+    }
+}
+
+pub fn profile_card(user: &User, notice: Option<&str>) -> Markup {
+    html! {
+        form .card #profile hx-post="/settings/profile" hx-swap="outerHTML" {
+            span .card-title {
+                "Profile"
+            }
+            @if let Some(notice) = notice {
+                p .callout { (notice) }
+            }
+            div .form-body {
+                input .title-input type="text" name="name" value=(user.name) placeholder="Display name" autocomplete="name" required;
+                input .title-input type="email" name="email" value=[user.email.as_deref()] placeholder="Email address" autocomplete="email";
+                @if user.email.is_some() {
+                    span .callout {
+                        @if user.email_verified { "Verified by your login" } @else { "Not verified" }
+                    }
+                }
+                input .title-input type="text" name="timezone" value=(user.timezone) placeholder="Timezone, e.g. Europe/London" list="timezones" required;
+                datalist #timezones {}
+                script {
+                    // Suggest every timezone the browser knows about
+                    (PreEscaped(r#"(function () {
+                        const list = document.getElementById("timezones");
+                        for (const zone of Intl.supportedValuesOf?.("timeZone") ?? []) {
+                            list.append(new Option(zone));
+                        }
+                    })();"#))
+                }
+                select .title-input name="week_start" {
+                    @for week_start in WeekStart::ALL {
+                        option value=(week_start.as_str()) selected[week_start == user.week_start] {
+                            "Weeks start on " (week_start.as_str())
+                        }
+                    }
+                }
+                input .title-input type="text" name="locale" value=(user.locale) placeholder="Language, e.g. en-GB" required;
+                button .create-button type="submit" { "Save" }
+            }
+        }
+    }
+}
+
+pub fn identities_card(accounts: &[Account], providers: &Providers) -> Markup {
+    let label = |name: &str| match providers.get(name) {
+        Some(provider) => provider.label.clone(),
+        None if name == PASSWORD_PROVIDER => "Password".to_string(),
+        None if name == EMAIL_PROVIDER => "Email".to_string(),
+        None => name.to_string(),
+    };
+    html! {
+        div .card #identities {
+            span .card-title {
+                "Linked logins"
+            }
+            ul .settings-rows {
+                @for account in accounts {
+                    li .settings-row {
+                        span {
+                            (label(&account.provider))
+                            span .callout { " " (account.id) }
+                        }
+                        // There must always be some way left to log in
+                        @if accounts.len() > 1 {
+                            form hx-post="/settings/identities/unlink" hx-target="#identities" hx-swap="outerHTML"
+                                hx-confirm={"Unlink your " (label(&account.provider)) " login?"} {
+                                input type="hidden" name="provider" value=(account.provider) {}
+                                input type="hidden" name="id" value=(account.id) {}
+                                button .danger-button type="submit" { "Unlink" }
+                            }
+                        }
+                    }
+                }
+            }
+            div .settings-actions {
+                @for provider in providers.iter() {
+                    a .secondary-button href={"/auth/" (provider.name) "?link=true"} {
+                        "Link " (provider.label)
+                    }
+                }
+            }
+        }
+    }
+}
+
+// `username` is set when the user already has a password login
+pub fn password_card(username: Option<&str>, notice: Option<&str>) -> Markup {
+    html! {
+        form .card #password hx-post="/settings/password" hx-swap="outerHTML" {
+            span .card-title {
+                @if username.is_some() { "Change password" } @else { "Set a password" }
+            }
+            @if let Some(notice) = notice {
+                p .callout { (notice) }
+            }
+            div .form-body {
+                @match username {
+                    Some(username) => {
+                        input type="hidden" name="username" value=(username) autocomplete="username";
+                        input .title-input type="password" name="current_password" placeholder="Current password" autocomplete="current-password" required;
+                    }
+                    None => {
+                        input .title-input type="text" name="username" placeholder="Username" autocomplete="username" required;
+                    }
+                }
+                input .title-input type="password" name="new_password" placeholder="New password" autocomplete="new-password" required;
+                input .title-input type="password" name="confirm_password" placeholder="Confirm new password" autocomplete="new-password" required;
+                button .create-button type="submit" { "Save" }
+            }
+        }
+    }
+}
+
+pub enum TotpCard<'a> {
+    Off,
+    Enrolling {
+        qr_code: &'a QrCode,
+        secret: &'a str,
+    },
+    On {
+        recovery_codes_left: i64,
+    },
+    // Shown once, straight after they're generated
+    NewRecoveryCodes(&'a [String]),
+}
+
+// Draws each dark module as a unit square, inside the standard 4 module quiet zone
+fn qr_code_svg(qr_code: &QrCode) -> Markup {
+    let width = qr_code.width();
+    let size = width + 8;
+    let path: String = qr_code
+        .to_colors()
+        .iter()
+        .enumerate()
+        .filter(|(_, color)| **color == Color::Dark)
+        .map(|(i, _)| format!("M{},{}h1v1h-1z", i % width + 4, i / width + 4))
+        .collect();
+    html! {
+        svg .qr-code xmlns="http://www.w3.org/2000/svg" viewBox={"0 0 " (size) " " (size)} shape-rendering="crispEdges" {
+            rect width=(size) height=(size) fill="#fff" {}
+            path d=(path) fill="#000" {}
+        }
+    }
+}
+
+pub fn totp_card(card: TotpCard, notice: Option<&str>) -> Markup {
+    html! {
+        div .card #totp {
+            span .card-title {
+                "Two-factor authentication"
+            }
+            @if let Some(notice) = notice {
+                p .callout { (notice) }
+            }
+            @match card {
+                TotpCard::Off => {
+                    p .callout { "Ask for a code from an authenticator app each time you log in" }
+                    div .settings-actions {
+                        button .secondary-button hx-post="/settings/totp/setup" hx-target="#totp" hx-swap="outerHTML" {
+                            "Set up"
+                        }
+                    }
+                }
+                TotpCard::Enrolling { qr_code, secret } => {
+                    p .callout { "Scan this with your authenticator app, then enter the code it shows" }
+                    (qr_code_svg(qr_code))
+                    p .callout { "Or enter this key: " code { (secret) } }
+                    form .form-body hx-post="/settings/totp/enable" hx-target="#totp" hx-swap="outerHTML" {
+                        input .title-input type="text" name="code" placeholder="Code" inputmode="numeric" autocomplete="one-time-code" required;
+                        button .create-button type="submit" { "Turn on" }
+                    }
+                }
+                TotpCard::On { recovery_codes_left } => {
+                    p .callout { "On, with " (recovery_codes_left) " recovery codes left" }
+                    form .form-body hx-target="#totp" hx-swap="outerHTML" {
+                        input .title-input type="text" name="code" placeholder="Code" autocomplete="one-time-code" required;
+                        div .settings-actions {
+                            button .secondary-button hx-post="/settings/totp/recovery-codes" {
+                                "New recovery codes"
+                            }
+                            button .secondary-button .danger-button hx-post="/settings/totp/disable"
+                                hx-confirm="Turn off two-factor authentication?" {
+                                "Turn off"
+                            }
+                        }
+                    }
+                }
+                TotpCard::NewRecoveryCodes(codes) => {
+                    p .callout {
+                        "Keep these recovery codes somewhere safe. Each can be used once in place of a code if you lose your authenticator, and they won't be shown again"
+                    }
+                    ul .recovery-codes {
+                        @for code in codes {
+                            li { code { (code) } }
+                        }
+                    }
+                    div .settings-actions {
+                        a .secondary-button href="/settings" { "Done" }
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn passkeys_card(passkeys: &[Passkey], notice: Option<&str>) -> Markup {
+    html! {
+        div .card #passkeys {
+            script src="/static/js/passkey.js" {}
+            span .card-title {
+                "Passkeys"
+            }
+            @if let Some(notice) = notice {
+                p .callout { (notice) }
+            }
+            ul .settings-rows {
+                @for passkey in passkeys {
+                    li .settings-row {
+                        form .form-row hx-post="/settings/passkeys/rename" hx-target="#passkeys" hx-swap="outerHTML" {
+                            input type="hidden" name="id" value=(passkey.id) {}
+                            input .title-input type="text" name="name" value=(passkey.name) required;
+                            button .secondary-button type="submit" { "Rename" }
+                        }
+                        span .callout {
+                            @match passkey.last_used_at {
+                                Some(last_used) => { "Last used " (last_used.date()) }
+                                None => { "Added " (passkey.created_at.date()) }
+                            }
+                        }
+                        form hx-post="/settings/passkeys/revoke" hx-target="#passkeys" hx-swap="outerHTML"
+                            hx-confirm={"Remove the passkey " (passkey.name) "?"} {
+                            input type="hidden" name="id" value=(passkey.id) {}
+                            button .danger-button type="submit" { "Remove" }
+                        }
+                    }
+                }
+            }
+            div .form-row {
+                input .title-input #passkey-name type="text" placeholder="Name, e.g. Laptop" maxlength="64";
+                button .create-button type="button" onclick="registerPasskey()" { "Add a passkey" }
+            }
+        }
+    }
+}
+
+// `new_token` is only ever shown straight after it's created
+pub fn api_tokens_card(tokens: &[ApiToken], new_token: Option<&str>) -> Markup {
+    html! {
+        div .card #api-tokens {
+            span .card-title {
+                "API tokens"
+            }
+            p .callout {
+                "Scripts can send a token as " code { "Authorization: Bearer <token>" } " instead of logging in"
+            }
+            @if let Some(token) = new_token {
+                p .callout { "Copy this token now, it won't be shown again" }
+                input .title-input type="text" value=(token) readonly onclick="this.select()";
+            }
+            ul .settings-rows {
+                @for token in tokens {
+                    li .settings-row {
+                        span {
+                            (token.name)
+                            span .callout {
+                                @match token.scope {
+                                    TokenScope::Read => { " read only" }
+                                    TokenScope::ReadWrite => { " read and write" }
+                                }
+                                @match token.expires_at {
+                                    _ if token.is_expired() => { ", expired" }
+                                    Some(expires_at) => { ", expires " (expires_at.date()) }
+                                    None => { ", never expires" }
+                                }
+                                @match token.last_used_at {
+                                    Some(last_used) => { ", last used " (last_used.date()) }
+                                    None => { ", created " (token.created_at.date()) }
+                                }
+                            }
+                        }
+                        form hx-post="/settings/tokens/revoke" hx-target="#api-tokens" hx-swap="outerHTML"
+                            hx-confirm={"Revoke the token " (token.name) "?"} {
+                            input type="hidden" name="id" value=(token.id) {}
+                            button .danger-button type="submit" { "Revoke" }
+                        }
+                    }
+                }
+            }
+            form .form-body hx-post="/settings/tokens" hx-target="#api-tokens" hx-swap="outerHTML" {
+                input .title-input type="text" name="name" placeholder="Token name" maxlength="64" required;
+                div .form-row {
+                    select .title-input name="scope" {
+                        option value="Read" { "Read only" }
+                        option value="ReadWrite" { "Read and write" }
+                    }
+                    select .title-input name="expires" {
+                        option value="30" { "30 days" }
+                        option value="90" { "90 days" }
+                        option value="365" { "1 year" }
+                        option value="never" { "Never expires" }
+                    }
+                }
+                button .create-button type="submit" { "Create token" }
+            }
+        }
+    }
+}
+
+// `current` is the session this page was loaded with, which can only be ended by logging out
+pub fn sessions_card(
+    sessions: &[ActiveSession],
+    current: Option<&str>,
+    notice: Option<&str>,
+) -> Markup {
+    html! {
+        div .card #sessions {
+            span .card-title {
+                "Active sessions"
+            }
+            @if let Some(notice) = notice {
+                p .callout { (notice) }
+            }
+            ul .settings-rows {
+                @for session in sessions {
+                    li .settings-row {
+                        span {
+                            (session.user_agent.as_deref().unwrap_or("Unknown browser"))
+                            span .callout {
+                                @if let Some(ip_address) = &session.ip_address {
+                                    " " (ip_address) ","
+                                }
+                                " logged in " (session.created_at.date())
+                                ", last seen " (session.last_seen_at.date())
+                            }
+                        }
+                        @if current == Some(session.id.as_str()) {
+                            span .callout { "This browser" }
+                        } @else {
+                            form hx-post="/settings/sessions/revoke" hx-target="#sessions" hx-swap="outerHTML"
+                                hx-confirm="Log out this session?" {
+                                input type="hidden" name="id" value=(session.id) {}
+                                button .danger-button type="submit" { "Log out" }
+                            }
+                        }
+                    }
+                }
+            }
+            @if sessions.len() > 1 {
+                div .settings-actions {
+                    button .secondary-button .danger-button hx-post="/settings/sessions/revoke-others"
+                        hx-target="#sessions" hx-swap="outerHTML" hx-confirm="Log out everywhere else?" {
+                        "Log out everywhere else"
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn admin_card() -> Markup {
+    html! {
+        div .card {
+            span .card-title {
+                "Administration"
+            }
+            p .callout { "Manage the users and invites on this instance" }
+            div .settings-actions {
+                a .secondary-button href="/admin" { "Open admin" }
+            }
+        }
+    }
+}
+
+// `confirmation` is the phrase that has to be typed to go through with it
+pub fn delete_account_card(confirmation: &str, notice: Option<&str>) -> Markup {
+    html! {
+        form .card #delete-account hx-post="/settings/delete" hx-swap="outerHTML"
+            hx-confirm="Delete your account and everything in it? This can't be undone" {
+            span .card-title {
+                "Delete account"
+            }
+            p .callout {
+                "This deletes your routines, entries, invites and logins straight away, and logs out every device. "
+                "Download a copy of your data first if you'd like to keep it."
+            }
+            @if let Some(notice) = notice {
+                p .callout { (notice) }
+            }
+            div .settings-actions {
+                a .secondary-button href="/settings/export" download { "Download my data" }
+            }
+            div .form-body {
+                input .title-input type="text" name="confirm" placeholder={"Type \"" (confirmation) "\""} autocomplete="off" required;
+                button .danger-button type="submit" { "Delete my account" }
+            }
+        }
+    }
+}
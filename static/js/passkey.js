@@ -0,0 +1,86 @@
+// Runs the browser half of the WebAuthn ceremonies. The server checks everything,
+// this only converts between base64url strings and the buffers the browser wants
+
+function fromBase64Url(value) {
+  const base64 = value.replace(/-/g, "+").replace(/_/g, "/");
+  const padded = base64 + "=".repeat((4 - (base64.length % 4)) % 4);
+  return Uint8Array.from(atob(padded), (c) => c.charCodeAt(0));
+}
+
+function toBase64Url(buffer) {
+  let binary = "";
+  new Uint8Array(buffer).forEach((b) => (binary += String.fromCharCode(b)));
+  return btoa(binary).replace(/\+/g, "-").replace(/\//g, "_").replace(/=+$/, "");
+}
+
+// Errors come back as a full page, so show it in place of this one
+async function showError(res) {
+  document.open();
+  document.write(await res.text());
+  document.close();
+}
+
+function postJson(url, body) {
+  return fetch(url, {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify(body),
+  });
+}
+
+window.registerPasskey = async function registerPasskey() {
+  const res = await fetch("/settings/passkeys/options", { method: "POST" });
+  if (!res.ok) return showError(res);
+  const options = await res.json();
+  options.challenge = fromBase64Url(options.challenge);
+  options.user.id = fromBase64Url(options.user.id);
+  options.excludeCredentials = options.excludeCredentials.map((c) => ({
+    ...c,
+    id: fromBase64Url(c.id),
+  }));
+
+  let credential;
+  try {
+    credential = await navigator.credentials.create({ publicKey: options });
+  } catch (e) {
+    // Cancelled, or this passkey is already registered
+    return;
+  }
+
+  const saved = await postJson("/settings/passkeys", {
+    name: document.getElementById("passkey-name").value,
+    id: credential.id,
+    client_data: toBase64Url(credential.response.clientDataJSON),
+    attestation_object: toBase64Url(credential.response.attestationObject),
+  });
+  if (!saved.ok) return showError(saved);
+  document.getElementById("passkeys").outerHTML = await saved.text();
+  htmx.process(document.getElementById("passkeys"));
+};
+
+window.loginWithPasskey = async function loginWithPasskey() {
+  const res = await fetch("/login/passkey/options", { method: "POST" });
+  if (!res.ok) return showError(res);
+  const options = await res.json();
+  options.challenge = fromBase64Url(options.challenge);
+
+  let credential;
+  try {
+    credential = await navigator.credentials.get({ publicKey: options });
+  } catch (e) {
+    return;
+  }
+
+  const login = await postJson("/login/passkey", {
+    id: credential.id,
+    client_data: toBase64Url(credential.response.clientDataJSON),
+    authenticator_data: toBase64Url(credential.response.authenticatorData),
+    signature: toBase64Url(credential.response.signature),
+    user_handle: credential.response.userHandle
+      ? toBase64Url(credential.response.userHandle)
+      : null,
+  });
+  if (!login.ok) return showError(login);
+  // The login redirects on success, so go wherever it ended up
+  window.location.href = login.url;
+};